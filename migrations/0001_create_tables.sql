@@ -0,0 +1,38 @@
+-- Lists such as sub-goals are stored as JSON text
+
+CREATE TABLE tasks (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    date TEXT NOT NULL,
+    completed INTEGER NOT NULL,
+    priority TEXT NOT NULL
+);
+
+CREATE TABLE comments (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    content TEXT NOT NULL
+);
+
+CREATE TABLE goals (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    due_date TEXT NOT NULL,
+    progress INTEGER NOT NULL,
+    sub_goals TEXT NOT NULL
+);
+
+CREATE TABLE bot_tasks (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    completed INTEGER NOT NULL,
+    is_pomodoro INTEGER NOT NULL
+);
+
+CREATE TABLE bot_goals (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    progress INTEGER NOT NULL
+);
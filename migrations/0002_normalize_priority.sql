@@ -0,0 +1,7 @@
+-- Priorities used to be free-form; keep the ones that match a Priority and make the rest medium
+
+UPDATE tasks SET priority = lower(trim(priority));
+UPDATE tasks SET priority = 'medium' WHERE priority NOT IN ('low', 'medium', 'high');
+
+UPDATE goals SET priority = lower(trim(priority));
+UPDATE goals SET priority = 'medium' WHERE priority NOT IN ('low', 'medium', 'high');
@@ -0,0 +1,5 @@
+-- next_task_id; empty until the first task is added
+CREATE TABLE counters (
+    name TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
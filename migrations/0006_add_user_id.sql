@@ -0,0 +1,53 @@
+-- Tasks, comments and goals belong to a user (the X-User-Id header); rows saved
+-- before users existed go to the default user. Bot data stays shared.
+
+CREATE TABLE tasks_by_user (
+    user_id TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    date TEXT NOT NULL,
+    completed INTEGER NOT NULL,
+    priority TEXT NOT NULL,
+    recurrence TEXT,
+    tags TEXT NOT NULL DEFAULT '[]',
+    PRIMARY KEY (user_id, id)
+);
+INSERT INTO tasks_by_user SELECT 'default', * FROM tasks ORDER BY rowid;
+DROP TABLE tasks;
+ALTER TABLE tasks_by_user RENAME TO tasks;
+
+CREATE TABLE comments_by_user (
+    user_id TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT NOT NULL,
+    PRIMARY KEY (user_id, id)
+);
+INSERT INTO comments_by_user SELECT 'default', * FROM comments ORDER BY rowid;
+DROP TABLE comments;
+ALTER TABLE comments_by_user RENAME TO comments;
+
+CREATE TABLE goals_by_user (
+    user_id TEXT NOT NULL,
+    id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    due_date TEXT NOT NULL,
+    progress INTEGER NOT NULL,
+    sub_goals TEXT NOT NULL,
+    PRIMARY KEY (user_id, id)
+);
+INSERT INTO goals_by_user SELECT 'default', * FROM goals ORDER BY rowid;
+DROP TABLE goals;
+ALTER TABLE goals_by_user RENAME TO goals;
+
+CREATE TABLE counters_by_user (
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value INTEGER NOT NULL,
+    PRIMARY KEY (user_id, name)
+);
+INSERT INTO counters_by_user SELECT 'default', * FROM counters;
+DROP TABLE counters;
+ALTER TABLE counters_by_user RENAME TO counters;
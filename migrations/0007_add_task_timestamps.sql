@@ -0,0 +1,3 @@
+-- RFC3339 timestamps set by the server; tasks stored before them keep empty strings
+ALTER TABLE tasks ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE tasks ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,19 @@
+-- Bot task ids become random UUIDs like bot goal ids, so they aren't reused after a
+-- delete. Existing tasks get a new version 4 UUID; pomodoro sessions aren't stored,
+-- so nothing else refers to the old ids.
+
+CREATE TABLE bot_tasks_by_uuid (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    completed INTEGER NOT NULL,
+    is_pomodoro INTEGER NOT NULL
+);
+INSERT INTO bot_tasks_by_uuid
+SELECT lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' ||
+       substr(lower(hex(randomblob(2))), 2) || '-' ||
+       substr('89ab', 1 + (abs(random()) % 4), 1) || substr(lower(hex(randomblob(2))), 2) || '-' ||
+       lower(hex(randomblob(6))),
+       title, completed, is_pomodoro
+FROM bot_tasks ORDER BY rowid;
+DROP TABLE bot_tasks;
+ALTER TABLE bot_tasks_by_uuid RENAME TO bot_tasks;
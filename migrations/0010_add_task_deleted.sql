@@ -0,0 +1,2 @@
+-- Soft-deleted tasks stay stored until the trash is emptied
+ALTER TABLE tasks ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Progress updates of a goal, as a JSON list like sub_goals
+ALTER TABLE goals ADD COLUMN history TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+-- NULL for comments stored before versioning, which count as version 0
+ALTER TABLE comments ADD COLUMN version INTEGER;
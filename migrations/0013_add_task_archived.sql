@@ -0,0 +1,2 @@
+-- Completed tasks moved out of the task list by POST /tasks/archive
+ALTER TABLE tasks ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
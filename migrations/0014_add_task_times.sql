@@ -0,0 +1,3 @@
+-- Optional times of day, as HH:MM or RFC3339 timestamps
+ALTER TABLE tasks ADD COLUMN start_time TEXT;
+ALTER TABLE tasks ADD COLUMN end_time TEXT;
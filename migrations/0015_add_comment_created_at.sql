@@ -0,0 +1,2 @@
+-- RFC3339 timestamp set by the server; comments stored before it keep an empty string
+ALTER TABLE comments ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,29 @@
+use chrono::Local;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+// Append-only record of every create/update/delete, one line per change:
+// `<RFC3339 timestamp> <entity> <action> <id>`
+pub struct AuditLog {
+    path: String,
+}
+
+impl AuditLog {
+    pub fn new(path: String) -> Self {
+        AuditLog { path }
+    }
+
+    // Failing to write the log never fails the request that triggered it
+    pub fn record(&self, entity: &str, action: &str, id: impl Display) {
+        let line = format!("{} {} {} {}\n", Local::now().to_rfc3339(), entity, action, id);
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            log::error!("Failed to write audit log {}: {}", self.path, e);
+        }
+    }
+}
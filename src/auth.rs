@@ -0,0 +1,51 @@
+use actix_web::{web, Error, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use std::collections::HashMap;
+
+use crate::ApiError;
+
+// Paths that stay reachable without a key, e.g. for load balancer probes
+const PUBLIC_PATHS: [&str; 1] = ["/health"];
+
+// None when API_KEY is unset, which leaves the API open for local development
+pub struct ApiKey(pub Option<String>);
+
+// Requires `Authorization: Bearer <API_KEY>` on every request except PUBLIC_PATHS.
+// Browsers can't set headers on WebSocket handshakes, so `/ws/*` also accepts `?token=<API_KEY>`;
+// the key then ends up in URLs, so keep those out of access logs where that matters.
+pub async fn require_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let expected = req.app_data::<web::Data<ApiKey>>().and_then(|key| key.0.clone());
+    let authorized = match expected {
+        Some(key) if !PUBLIC_PATHS.contains(&req.path()) => {
+            let bearer = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::to_string);
+            let token = bearer.or_else(|| query_token(&req));
+            token.is_some_and(|token| token == key)
+        }
+        _ => true,
+    };
+
+    if !authorized {
+        let body = ApiError { error: "unauthorized", detail: "Missing or invalid API key".to_string() };
+        return Ok(req.into_response(HttpResponse::Unauthorized().json(body)).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+fn query_token(req: &ServiceRequest) -> Option<String> {
+    if !req.path().starts_with("/ws/") {
+        return None;
+    }
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok()?;
+    query.get("token").cloned()
+}
@@ -0,0 +1,105 @@
+use std::env;
+use std::io;
+
+// Server settings read from the environment at startup
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub database_url: String,
+    // None (ALLOWED_ORIGINS unset) means any origin is accepted, for local development
+    pub allowed_origins: Option<Vec<String>>,
+    // Requests allowed per client IP per minute
+    pub rate_limit_per_minute: u32,
+    // Bearer token required on data endpoints; None leaves the API unauthenticated
+    pub api_key: Option<String>,
+    // File that every create/update/delete is appended to
+    pub audit_log_path: String,
+    // Where the music category files are served from
+    pub music_base_url: String,
+    // Largest JSON request body accepted, in bytes
+    pub max_body_bytes: usize,
+    // Turns on development-only endpoints such as /admin/reset
+    pub dev_mode: bool,
+    // Whether a fresh install starts with the sample comments
+    pub seed_data: bool,
+    // Number of worker threads; None keeps actix's default of one per CPU core
+    pub workers: Option<usize>,
+}
+
+impl Config {
+    pub fn from_env() -> io::Result<Config> {
+        let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
+        let port = match env::var("PORT") {
+            Ok(port) => port.parse().map_err(|_| invalid("PORT", &port, "a port number (0-65535)"))?,
+            Err(_) => 8080,
+        };
+        let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://data.db".to_string());
+        let allowed_origins = env::var("ALLOWED_ORIGINS").ok().map(|origins| {
+            origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect()
+        });
+        let rate_limit_per_minute = match env::var("RATE_LIMIT_PER_MINUTE") {
+            Ok(limit) => match limit.parse() {
+                Ok(n) if n >= 1 => n,
+                _ => return Err(invalid("RATE_LIMIT_PER_MINUTE", &limit, "a whole number of at least 1")),
+            },
+            Err(_) => 120,
+        };
+        let api_key = env::var("API_KEY").ok().filter(|key| !key.is_empty());
+        let audit_log_path = env::var("AUDIT_LOG_PATH").unwrap_or_else(|_| "audit.log".to_string());
+        let music_base_url = env::var("MUSIC_BASE_URL")
+            .unwrap_or_else(|_| "https://ritika12df.github.io/ritikaaudio/".to_string());
+        let max_body_bytes = match env::var("MAX_BODY_BYTES") {
+            Ok(limit) => match limit.parse() {
+                Ok(n) if n >= 1 => n,
+                _ => return Err(invalid("MAX_BODY_BYTES", &limit, "a whole number of at least 1")),
+            },
+            Err(_) => 256 * 1024,
+        };
+        let dev_mode = flag("DEV_MODE")?;
+        let seed_data = flag("SEED_DATA")?;
+        let workers = match env::var("WORKERS") {
+            Ok(workers) => match workers.parse() {
+                Ok(n) if n >= 1 => Some(n),
+                _ => return Err(invalid("WORKERS", &workers, "a whole number of at least 1")),
+            },
+            Err(_) => None,
+        };
+        Ok(Config {
+            host,
+            port,
+            database_url,
+            allowed_origins,
+            rate_limit_per_minute,
+            api_key,
+            audit_log_path,
+            music_base_url,
+            max_body_bytes,
+            dev_mode,
+            seed_data,
+            workers,
+        })
+    }
+}
+
+// Boolean switches default to off when unset
+fn flag(name: &str) -> io::Result<bool> {
+    match env::var(name) {
+        Ok(value) => match value.as_str() {
+            "1" | "true" => Ok(true),
+            "0" | "false" | "" => Ok(false),
+            _ => Err(invalid(name, &value, "true or false")),
+        },
+        Err(_) => Ok(false),
+    }
+}
+
+fn invalid(name: &str, value: &str, expected: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid {} {:?}: expected {}", name, value, expected),
+    )
+}
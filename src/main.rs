@@ -1,383 +1,3309 @@
-use actix_cors::Cors;
-use actix_web::{get, post, put, delete, App, HttpServer, Responder, HttpResponse, web, middleware};
-use serde::{Serialize, Deserialize};
-use chrono::{Local, Datelike, NaiveDate};
-use std::sync::Mutex;
-use uuid::Uuid;
-
-// Existing types and state
-#[derive(Serialize)]
-struct DateResponse {
-    day: u32,
-    month: u32,
-    year: i32,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct Task {
-    id: Option<u32>,
-    title: String,
-    date: String,
-    completed: bool,
-    priority: String,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-struct Comment {
-    id: Option<u32>,
-    title: String,
-    content: String,
-}
-
-#[derive(Serialize, Deserialize, Clone)] 
-struct Goal {
-    id: Uuid,
-    title: String,
-    description: String,
-    priority: String,
-    due_date: String,
-    progress: u8,
-    sub_goals: Vec<SubGoal>,
-}
-
-#[derive(Serialize, Deserialize, Clone)] 
-struct SubGoal {
-    id: Uuid,
-    title: String,
-    completed: bool,
-    progress: u8,
-}
-
-#[derive(Serialize, Deserialize)]
-struct CreateGoal {
-    title: String,
-    description: String,
-    priority: String,
-    due_date: String, 
-}
-
-#[derive(Serialize, Deserialize)]
-struct UpdateProgress {
-    progress: u8,
-}
-
-// Music endpoint
-#[derive(Serialize)]
-struct MusicResponse {
-    url: String,
-}
-
-// State for main application
-struct AppState {
-    tasks: Mutex<Vec<Task>>,
-    comments: Mutex<Vec<Comment>>,
-    goals: Mutex<Vec<Goal>>,
-}
-
-// Bot-related types and state
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct BotTask {
-    id: Option<u32>,
-    title: String,
-    completed: bool,
-    is_pomodoro: bool,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct BotGoal {
-    id: Option<Uuid>,
-    title: String,
-    progress: u32,
-}
-
-pub struct BotAppState {
-    tasks: Mutex<Vec<BotTask>>,
-    goals: Mutex<Vec<BotGoal>>,
-}
-
-// Main application routes
-#[get("/current-date")]
-async fn current_date() -> impl Responder {
-    let now = Local::now();
-    let date = DateResponse {
-        day: now.day(),
-        month: now.month(),
-        year: now.year(),
-    };
-    HttpResponse::Ok().json(date)
-}
-
-#[get("/tasks")]
-async fn get_tasks(data: web::Data<AppState>) -> impl Responder {
-    let tasks = data.tasks.lock().unwrap();
-    HttpResponse::Ok().json(tasks.clone())
-}
-
-#[post("/tasks")]
-async fn add_task(task: web::Json<Task>, data: web::Data<AppState>) -> impl Responder {
-    println!("Received task: {:?}", task);
-    let mut tasks = data.tasks.lock().unwrap();
-    let mut new_task = task.into_inner();
-    new_task.id = Some(tasks.len() as u32 + 1);
-    
-    // Convert the user-friendly date to an actual date
-    new_task.date = match new_task.date.as_str() {
-        "Today" => Local::now().date_naive().to_string(),
-        "Tomorrow" => (Local::now().date_naive() + chrono::Duration::days(1)).to_string(),
-        "This Week" => (Local::now().date_naive() + chrono::Duration::days(7)).to_string(),
-        "This Month" => {
-            let today = Local::now().date_naive();
-            let next_month = if today.month() == 12 {
-                NaiveDate::from_ymd_opt(today.year() + 1, 1, today.day()).unwrap_or(today)
-            } else {
-                NaiveDate::from_ymd_opt(today.year(), today.month() + 1, today.day()).unwrap_or(today)
-            };
-            next_month.to_string()
-        },
-        _ => new_task.date, // Keep the original string if it's not one of the special cases
-    };
-
-    tasks.push(new_task.clone());
-    HttpResponse::Ok().json(new_task)
-}
-
-#[post("/tasks/complete/{id}")]
-async fn complete_task(task_id: web::Path<u32>, data: web::Data<AppState>) -> impl Responder {
-    let mut tasks = data.tasks.lock().unwrap();
-    let task_id = task_id.into_inner();
-    
-    // Use `filter` to unwrap the Option and compare
-    if let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id)) {
-        task.completed = true;
-    }
-    
-    HttpResponse::Ok().json(tasks.clone())
-}
-
-#[get("/comments")]
-async fn get_comments(data: web::Data<AppState>) -> impl Responder {
-    let comments = data.comments.lock().unwrap();
-    HttpResponse::Ok().json(comments.clone())
-}
-
-#[post("/comments")]
-async fn add_comment(comment: web::Json<Comment>, data: web::Data<AppState>) -> impl Responder {
-    let mut comments = data.comments.lock().unwrap();
-    let mut new_comment = comment.into_inner();
-    new_comment.id = Some(comments.len() as u32 + 1);
-    comments.push(new_comment.clone());
-    HttpResponse::Ok().json(new_comment)
-}
-
-#[put("/comments/{id}")]
-async fn update_comment(
-    path: web::Path<u32>,
-    comment: web::Json<Comment>,
-    data: web::Data<AppState>
-) -> impl Responder {
-    let id = path.into_inner();
-    let mut comments = data.comments.lock().unwrap();
-    if let Some(existing_comment) = comments.iter_mut().find(|c| c.id == Some(id)) {
-        *existing_comment = comment.into_inner();
-        existing_comment.id = Some(id);
-        HttpResponse::Ok().json(existing_comment.clone())
-    } else {
-        HttpResponse::NotFound().finish()
-    }
-}
-
-#[get("/goals")]
-async fn get_goals(data: web::Data<AppState>) -> impl Responder {
-    let goals = data.goals.lock().unwrap();
-    HttpResponse::Ok().json(&*goals)
-}
-
-#[post("/goals")]
-async fn create_goal(data: web::Data<AppState>, goal: web::Json<CreateGoal>) -> impl Responder {
-    let mut goals = data.goals.lock().unwrap();
-    let new_goal = Goal {
-        id: Uuid::new_v4(),
-        title: goal.title.clone(),
-        description: goal.description.clone(),
-        priority: goal.priority.clone(),
-        due_date: goal.due_date.clone(), // Use `due_date` here
-        progress: 0,
-        sub_goals: Vec::new(),
-    };
-    goals.push(new_goal.clone());
-    HttpResponse::Ok().json(new_goal)
-}
-
-#[put("/goals/{id}/progress")]
-async fn update_progress(
-    data: web::Data<AppState>,
-    path: web::Path<Uuid>,
-    progress: web::Json<UpdateProgress>,
-) -> impl Responder {
-    let id = path.into_inner(); // Destructure `web::Path` here
-    let mut goals = data.goals.lock().unwrap();
-    if let Some(goal) = goals.iter_mut().find(|g| g.id == id) {
-        goal.progress = progress.progress;
-        HttpResponse::Ok().json(goal)
-    } else {
-        HttpResponse::NotFound().finish()
-    }
-}
-
-#[get("/api/music/{category}")]
-async fn get_music(category: web::Path<String>) -> impl Responder {
-    // Map categories to music URLs
-    let url = match category.as_str() {
-        "Relax" => "https://ritika12df.github.io/ritikaaudio/relax.mp3",
-        "Focus" => "https://ritika12df.github.io/ritikaaudio/focus.mp3",
-        "Energize" => "https://ritika12df.github.io/ritikaaudio/energize.mp3",
-        "Sleep" => "https://ritika12df.github.io/ritikaaudio/sleep.mp3",
-        "Meditate" => "https://ritika12df.github.io/ritikaaudio/meditate.mp3",
-        _ => "https://ritika12df.github.io/ritikaaudio/default.mp3",
-    };
-
-    HttpResponse::Ok().json(MusicResponse { url: url.to_string() })
-}
-
-// Bot routes
-#[get("/bot/tasks")]
-async fn get_bot_tasks(data: web::Data<BotAppState>) -> impl Responder {
-    let tasks = data.tasks.lock().unwrap();
-    HttpResponse::Ok().json(tasks.clone())
-}
-
-#[post("/bot/tasks")]
-async fn add_bot_task(task: web::Json<BotTask>, data: web::Data<BotAppState>) -> impl Responder {
-    let mut tasks = data.tasks.lock().unwrap();
-    let mut new_task = task.into_inner();
-    new_task.id = Some(tasks.len() as u32 + 1);
-    tasks.push(new_task.clone());
-    HttpResponse::Ok().json(new_task)
-}
-
-// Update bot task
-#[put("/bot/tasks/{id}")]
-async fn update_bot_task(
-    path: web::Path<u32>,
-    task: web::Json<BotTask>,
-    data: web::Data<BotAppState>
-) -> impl Responder {
-    let id = path.into_inner();
-    let mut tasks = data.tasks.lock().unwrap();
-    
-    // Find the task with the provided ID and update it
-    if let Some(existing_task) = tasks.iter_mut().find(|t| t.id == Some(id)) {
-        existing_task.title = task.title.clone();
-        existing_task.completed = task.completed;
-        existing_task.is_pomodoro = task.is_pomodoro;
-        HttpResponse::Ok().json(existing_task.clone())
-    } else {
-        HttpResponse::NotFound().finish()
-    }
-}
-
-#[post("/bot/tasks/complete/{id}")]
-async fn complete_bot_task(task_id: web::Path<u32>, data: web::Data<BotAppState>) -> impl Responder {
-    let mut tasks = data.tasks.lock().unwrap();
-    let task_id = task_id.into_inner();
-    
-    if let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id)) {
-        task.completed = true;
-        HttpResponse::Ok().json(task.clone())
-    } else {
-        HttpResponse::NotFound().finish()
-    }
-}
-
-
-#[get("/bot/goals")]
-async fn get_bot_goals(data: web::Data<BotAppState>) -> impl Responder {
-    let goals = data.goals.lock().unwrap();
-    HttpResponse::Ok().json(goals.clone())
-}
-
-#[post("/bot/goals")]
-async fn add_bot_goal(
-    goal: web::Json<BotGoal>,
-    data: web::Data<BotAppState>
-) -> impl Responder {
-    let mut goals = data.goals.lock().unwrap();
-    let mut new_goal = goal.into_inner();
-    new_goal.id = Some(Uuid::new_v4()); // Assign a new UUID
-    goals.push(new_goal.clone());
-    HttpResponse::Ok().json(new_goal)
-}
-
-#[delete("/bot/tasks/{id}")]
-async fn delete_bot_task(task_id: web::Path<u32>, data: web::Data<BotAppState>) -> impl Responder {
-    let mut tasks = data.tasks.lock().unwrap();
-    let task_id = task_id.into_inner();
-    if tasks.iter().position(|task| task.id == Some(task_id)).is_some() {
-        tasks.retain(|task| task.id != Some(task_id));
-        HttpResponse::Ok().finish()
-    } else {
-        HttpResponse::NotFound().finish()
-    }
-}
-
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let app_state = web::Data::new(AppState {
-        tasks: Mutex::new(vec![]),
-        comments: Mutex::new(vec![
-            Comment {
-                id: Some(1),
-                title: "Market research".to_string(),
-                content: "Find my keynote attached...".to_string(),
-            },
-            Comment {
-                id: Some(2),
-                title: "Market research".to_string(),
-                content: "I've added the data...".to_string(),
-            },
-        ]),
-        goals: Mutex::new(Vec::new()),
-    });
-
-    let bot_state = web::Data::new(BotAppState {
-        tasks: Mutex::new(vec![]),
-        goals: Mutex::new(vec![]),
-    });
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::JsonConfig::default().error_handler(|err, _req| {
-                actix_web::error::ErrorBadRequest(format!("JSON Error: {}", err))
-            }))
-            .app_data(app_state.clone())
-            .app_data(bot_state.clone())
-            .wrap(middleware::Logger::default())
-            .wrap(Cors::default()
-                .allow_any_origin()
-                .allow_any_method()
-                .allow_any_header()
-            )
-            .service(current_date)
-            .service(get_tasks)
-            .service(add_task)
-            .service(complete_task)
-            .service(get_comments)
-            .service(add_comment)
-            .service(update_comment)
-            .service(get_goals)
-            .service(create_goal)
-            .service(update_progress)
-            .service(get_bot_tasks)
-            .service(add_bot_task)
-            .service(update_bot_task)
-            .service(complete_bot_task)
-            .service(delete_bot_task) // Added delete route
-            .service(get_bot_goals)
-            .service(add_bot_goal)
-            .service(get_music) // Add the music endpoint here
-
-    })
-    .bind("0.0.0.0:8080")?
-    .run()
-    .await
-}
+use actix_cors::Cors;
+use actix_web::{get, post, put, patch, delete, App, HttpServer, HttpRequest, Responder, HttpResponse, web, middleware};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, FixedOffset, Local, Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+use futures_util::{stream, StreamExt};
+use sqlx::SqlitePool;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU32, Ordering};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+mod audit;
+mod auth;
+mod config;
+mod metrics;
+mod persistence;
+mod rate_limit;
+mod request_id;
+mod user;
+mod ws;
+
+use user::User;
+
+// Existing types and state
+#[derive(Serialize)]
+struct DateResponse {
+    day: u32,
+    month: u32,
+    year: i32,
+}
+
+// Variants are declared lowest first so the derived ordering has High > Medium > Low
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+impl Priority {
+    // Same spelling as the serde representation
+    fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        }
+    }
+}
+
+// Used for tasks and goals created without a priority
+const DEFAULT_PRIORITY: Priority = Priority::Medium;
+
+impl Default for Priority {
+    fn default() -> Self {
+        DEFAULT_PRIORITY
+    }
+}
+
+// A blank priority falls back to DEFAULT_PRIORITY instead of rejecting the body.
+// Case is ignored, so "High" and "HIGH" in bodies and query params both mean high.
+impl<'de> Deserialize<'de> for Priority {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        match value.trim().to_lowercase().as_str() {
+            "" => Ok(DEFAULT_PRIORITY),
+            "low" => Ok(Priority::Low),
+            "medium" => Ok(Priority::Medium),
+            "high" => Ok(Priority::High),
+            _ => Err(serde::de::Error::unknown_variant(value.trim(), &["low", "medium", "high"])),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Recurrence {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Task {
+    id: Option<u32>,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    date: String,
+    completed: bool,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default)]
+    tags: Vec<String>,
+    // Time of day as HH:MM or an RFC3339 timestamp; see `parse_time`
+    #[serde(default)]
+    start_time: Option<String>,
+    #[serde(default)]
+    end_time: Option<String>,
+    // RFC3339 timestamps set by the server; empty for tasks stored before they existed
+    #[serde(default)]
+    created_at: String,
+    #[serde(default)]
+    updated_at: String,
+    // Set by DELETE /tasks/{id}; such tasks only show up in /tasks/trash until restored or purged
+    #[serde(default)]
+    deleted: bool,
+}
+
+#[derive(Deserialize)]
+struct UpcomingQuery {
+    days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct AddTaskQuery {
+    dedup: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ArchiveQuery {
+    days: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ArchivedCount {
+    archived: usize,
+}
+
+#[derive(Deserialize)]
+struct DateRange {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+struct SnoozeTask {
+    days: i64,
+}
+
+#[derive(Deserialize)]
+struct AddTag {
+    tag: String,
+}
+
+#[derive(Serialize)]
+struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+#[derive(Serialize, Default)]
+struct PriorityCounts {
+    low: usize,
+    medium: usize,
+    high: usize,
+}
+
+#[derive(Serialize, Default)]
+struct TaskStats {
+    total: usize,
+    completed: usize,
+    pending: usize,
+    by_priority: PriorityCounts,
+}
+
+#[derive(Serialize)]
+struct BatchCompleteResult {
+    updated: Vec<u32>,
+    not_found: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct RemovedCount {
+    removed: usize,
+}
+
+// Pushed to /ws/tasks clients, e.g. { "event": "added", "task": { ... } }
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum TaskEvent {
+    Added { task: Task },
+    Updated { task: Task },
+    Completed { task: Task },
+    Deleted { id: u32 },
+}
+
+#[derive(Serialize)]
+struct Streak {
+    current_streak: u32,
+    longest_streak: u32,
+}
+
+#[derive(Serialize)]
+struct TaskConflict {
+    first: Task,
+    second: Task,
+}
+
+#[derive(Serialize)]
+struct RecurringCompletion {
+    completed: Task,
+    next: Task,
+}
+
+#[derive(Deserialize)]
+struct TaskFilter {
+    completed: Option<bool>,
+    priority: Option<Priority>,
+    date: Option<String>,
+    tag: Option<String>,
+    sort: Option<String>,
+}
+
+// How long an Idempotency-Key on POST /tasks keeps pointing at the task it created
+const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(Deserialize)]
+struct Pagination {
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Page<T> {
+    items: Vec<T>,
+    total: usize,
+    page: usize,
+    per_page: usize,
+}
+
+#[derive(Deserialize)]
+struct CommentFilter {
+    title: Option<String>,
+    title_contains: Option<String>,
+    order: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Comment {
+    id: Option<u32>,
+    title: String,
+    content: String,
+    // Bumped on every update; an update carrying a different version is rejected with 409.
+    // Updates that leave it out skip the check.
+    #[serde(default)]
+    version: Option<u32>,
+    // RFC3339, set by the server; empty for comments stored before it existed
+    #[serde(default)]
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)] 
+struct Goal {
+    id: Uuid,
+    title: String,
+    description: String,
+    priority: Priority,
+    due_date: String,
+    progress: u8,
+    sub_goals: Vec<SubGoal>,
+    // One entry per PUT /goals/{id}/progress, oldest first
+    #[serde(default)]
+    history: Vec<ProgressEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ProgressEntry {
+    timestamp: String,
+    progress: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone)] 
+struct SubGoal {
+    id: Uuid,
+    title: String,
+    completed: bool,
+    progress: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CreateGoal {
+    title: String,
+    description: String,
+    #[serde(default)]
+    priority: Priority,
+    due_date: String, 
+}
+
+#[derive(Deserialize)]
+struct AllowPastQuery {
+    allow_past: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct CloneGoal {
+    due_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GoalQuery {
+    sort: Option<String>,
+    priority: Option<Priority>,
+    min_progress: Option<u8>,
+    max_progress: Option<u8>,
+}
+
+// Fields left out of the body are kept as they are
+#[derive(Serialize, Deserialize)]
+struct PatchGoal {
+    title: Option<String>,
+    description: Option<String>,
+    priority: Option<Priority>,
+    due_date: Option<String>,
+}
+
+// A goal as listed by GET /goals, with its computed urgency alongside the stored fields
+#[derive(Serialize)]
+struct RankedGoal {
+    #[serde(flatten)]
+    goal: Goal,
+    urgency: f64,
+}
+
+#[derive(Serialize)]
+struct GoalRemaining {
+    id: Uuid,
+    title: String,
+    remaining: u8,
+}
+
+#[derive(Serialize)]
+struct RemainingWork {
+    goals: Vec<GoalRemaining>,
+    total_remaining: u32,
+}
+
+#[derive(Serialize)]
+struct MovedSubGoal {
+    from: Goal,
+    to: Goal,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CreateSubGoal {
+    title: String,
+}
+
+const MAX_PROGRESS: u8 = 100;
+
+#[derive(Serialize, Deserialize)]
+struct UpdateProgress {
+    progress: u8,
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    uptime_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchResults {
+    tasks: Vec<Task>,
+    comments: Vec<Comment>,
+}
+
+// Upper bound on comment content, in characters
+const MAX_COMMENT_LENGTH: usize = 5000;
+
+#[derive(Serialize)]
+struct CountResponse {
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct DailySummary {
+    tasks_due_today: usize,
+    overdue_tasks: usize,
+    goals_due_this_week: usize,
+    avg_goal_progress: f64,
+}
+
+// Combined backup of a user's tasks, comments and goals, see /export
+#[derive(Serialize, Deserialize)]
+struct ExportData {
+    tasks: Vec<Task>,
+    comments: Vec<Comment>,
+    goals: Vec<Goal>,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    merge: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ImportSummary {
+    tasks: usize,
+    comments: usize,
+    goals: usize,
+}
+
+// Music endpoint
+// Category -> file name under the music base URL (MUSIC_BASE_URL)
+const MUSIC_CATEGORIES: [(&str, &str); 6] = [
+    ("Relax", "relax.mp3"),
+    ("Focus", "focus.mp3"),
+    ("Energize", "energize.mp3"),
+    ("Sleep", "sleep.mp3"),
+    ("Meditate", "meditate.mp3"),
+    ("default", "default.mp3"),
+];
+
+#[derive(Serialize)]
+struct MusicResponse {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct MusicCategory {
+    category: String,
+    url: String,
+}
+
+// Tasks, comments and goals of one user; handlers get them through the User extractor.
+// The collections cache what's stored in `db`; handlers that change them hold `writes`
+// until the changed rows are saved
+struct UserData {
+    tasks: Mutex<Vec<Task>>,
+    // Next id handed out by add_task; only ever increases so deleted ids aren't reused
+    next_task_id: AtomicU32,
+    task_updates: ws::Broadcaster,
+    // Idempotency-Key -> id of the task it created and when, see IDEMPOTENCY_WINDOW
+    idempotency_keys: Mutex<HashMap<String, (u32, Instant)>>,
+    // Completed tasks moved out of `tasks` by POST /tasks/archive
+    archived_tasks: Mutex<Vec<Task>>,
+    comments: Mutex<Vec<Comment>>,
+    // Next id handed out by add_comment; like next_task_id it only ever increases
+    next_comment_id: AtomicU32,
+    goals: Mutex<Vec<Goal>>,
+    // Receives the full goal JSON whenever a goal is edited or its progress changes
+    goal_updates: ws::Broadcaster,
+    writes: tokio::sync::Mutex<()>,
+}
+
+impl UserData {
+    fn new(stored: persistence::StoredData) -> UserData {
+        // Older databases and data files have no counter, so never hand out an id that's already taken
+        let max_task_id = stored.tasks.iter().chain(&stored.archived_tasks).filter_map(|t| t.id).max().unwrap_or(0);
+        let max_comment_id = stored.comments.iter().filter_map(|c| c.id).max().unwrap_or(0);
+        UserData {
+            next_task_id: AtomicU32::new(stored.next_task_id.max(max_task_id + 1)),
+            next_comment_id: AtomicU32::new(stored.next_comment_id.max(max_comment_id + 1)),
+            tasks: Mutex::new(stored.tasks),
+            task_updates: ws::Broadcaster::new(),
+            idempotency_keys: Mutex::new(HashMap::new()),
+            archived_tasks: Mutex::new(stored.archived_tasks),
+            comments: Mutex::new(stored.comments),
+            goals: Mutex::new(stored.goals),
+            goal_updates: ws::Broadcaster::new(),
+            writes: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl Default for UserData {
+    fn default() -> UserData {
+        UserData::new(persistence::StoredData::default())
+    }
+}
+
+// State for main application
+struct AppState {
+    // Only users that have stored something are kept here
+    users: Mutex<HashMap<String, Arc<UserData>>>,
+    started_at: Instant,
+    db: SqlitePool,
+    audit: Arc<audit::AuditLog>,
+    music_base_url: String,
+    // Enables /admin/reset (DEV_MODE)
+    dev_mode: bool,
+    // Start from the sample comments on a fresh install or reset (SEED_DATA)
+    seed_data: bool,
+}
+
+// Bot-related types and state
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct BotTask {
+    id: Option<Uuid>,
+    title: String,
+    completed: bool,
+    is_pomodoro: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct BotGoal {
+    id: Option<Uuid>,
+    title: String,
+    progress: u32,
+}
+
+#[derive(Deserialize)]
+struct BotTaskFilter {
+    pomodoro: Option<bool>,
+    completed: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct BatchDeleteResult {
+    deleted: Vec<Uuid>,
+    not_found: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UpdateBotProgress {
+    progress: u32,
+}
+
+struct PomodoroSession {
+    task_id: Uuid,
+    started_at: DateTime<Local>,
+    ended_at: Option<DateTime<Local>>,
+}
+
+#[derive(Serialize)]
+struct PomodoroSummary {
+    task_id: Uuid,
+    session_count: usize,
+    focus_minutes: i64,
+    active: bool,
+}
+
+pub struct BotAppState {
+    tasks: Mutex<Vec<BotTask>>,
+    goals: Mutex<Vec<BotGoal>>,
+    // Kept in memory only; unlike the tasks and goals it isn't stored in `db`
+    pomodoro_sessions: Mutex<Vec<PomodoroSession>>,
+    db: SqlitePool,
+    writes: tokio::sync::Mutex<()>,
+    audit: Arc<audit::AuditLog>,
+}
+
+fn pomodoro_summary(task_id: Uuid, sessions: &[PomodoroSession]) -> PomodoroSummary {
+    let mut summary = PomodoroSummary { task_id, session_count: 0, focus_minutes: 0, active: false };
+    let mut focus_secs = 0;
+    for session in sessions.iter().filter(|s| s.task_id == task_id) {
+        match session.ended_at {
+            Some(ended_at) => {
+                summary.session_count += 1;
+                focus_secs += (ended_at - session.started_at).num_seconds();
+            }
+            None => summary.active = true,
+        }
+    }
+    summary.focus_minutes = focus_secs / 60;
+    summary
+}
+
+// Body returned for every error response, e.g. { "error": "not_found", "detail": "Task not found" }
+#[derive(Serialize)]
+struct ApiError {
+    error: &'static str,
+    detail: String,
+}
+
+impl ApiError {
+    fn not_found(detail: impl Into<String>) -> HttpResponse {
+        HttpResponse::NotFound().json(ApiError { error: "not_found", detail: detail.into() })
+    }
+
+    fn bad_request(detail: impl Into<String>) -> HttpResponse {
+        HttpResponse::BadRequest().json(ApiError { error: "bad_request", detail: detail.into() })
+    }
+
+    fn conflict(detail: impl Into<String>) -> HttpResponse {
+        HttpResponse::Conflict().json(ApiError { error: "conflict", detail: detail.into() })
+    }
+
+    // The change couldn't be written to the database, so it wasn't made
+    fn storage_failed() -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .json(ApiError { error: "storage_error", detail: "Failed to save changes".to_string() })
+    }
+}
+
+// A panic while a lock is held poisons it; the Vec inside is still usable,
+// so recover the guard instead of failing every later request
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Convert the user-friendly date to an actual date.
+// Anything else must already be a YYYY-MM-DD date, otherwise None is returned.
+fn normalize_date(date: &str) -> Option<NaiveDate> {
+    let normalized = match date {
+        "Today" => Local::now().date_naive(),
+        "Tomorrow" => Local::now().date_naive() + chrono::Duration::days(1),
+        "This Week" => Local::now().date_naive() + chrono::Duration::days(7),
+        "This Month" => {
+            let today = Local::now().date_naive();
+            if today.month() == 12 {
+                NaiveDate::from_ymd_opt(today.year() + 1, 1, today.day()).unwrap_or(today)
+            } else {
+                NaiveDate::from_ymd_opt(today.year(), today.month() + 1, today.day()).unwrap_or(today)
+            }
+        },
+        _ => return parse_date(date),
+    };
+    Some(normalized)
+}
+
+// Pages are 1-based; pages past the end come back with no items
+fn paginate<T>(items: Vec<T>, pagination: &Pagination) -> Page<T> {
+    let page = pagination.page.unwrap_or(1).max(1);
+    let per_page = pagination.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let total = items.len();
+    let items = items
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+    Page { items, total, page, per_page }
+}
+
+fn next_occurrence(date: NaiveDate, recurrence: Recurrence) -> Option<NaiveDate> {
+    match recurrence {
+        Recurrence::Daily => date.checked_add_signed(chrono::Duration::days(1)),
+        Recurrence::Weekly => date.checked_add_signed(chrono::Duration::days(7)),
+        Recurrence::Monthly => date.checked_add_months(chrono::Months::new(1)),
+    }
+}
+
+// Tags are stored trimmed and lowercase so "Work" and "work " are the same tag
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|t| normalize_tag(t)).filter(|t| !t.is_empty()) {
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+// Titles are stored trimmed; whitespace-only titles are rejected
+fn trimmed_title(title: &str, kind: &str) -> Result<String, String> {
+    let title = title.trim();
+    if title.is_empty() {
+        Err(format!("{} title must not be empty", kind))
+    } else {
+        Ok(title.to_string())
+    }
+}
+
+fn accepts_ndjson(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/x-ndjson"))
+}
+
+// Quoted hash of a response body, used as its ETag
+fn etag_for(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Whether `If-None-Match` lists the given ETag (or `*`); weak validators compare equal
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim())
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
+}
+
+// Accepts HH:MM or an RFC3339 timestamp, whose time of day is used as written
+// A task time: a time of day on the task's date, or a full timestamp
+#[derive(Clone, Copy)]
+enum TaskTime {
+    Clock(NaiveTime),
+    Stamp(DateTime<FixedOffset>),
+}
+
+impl TaskTime {
+    // Timestamps keep their own date and time as written
+    fn on(self, date: NaiveDate) -> NaiveDateTime {
+        match self {
+            TaskTime::Clock(time) => date.and_time(time),
+            TaskTime::Stamp(stamp) => stamp.naive_local(),
+        }
+    }
+
+    fn time_of_day(self) -> NaiveTime {
+        match self {
+            TaskTime::Clock(time) => time,
+            TaskTime::Stamp(stamp) => stamp.time(),
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Option<TaskTime> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .ok()
+        .map(TaskTime::Clock)
+        .or_else(|| DateTime::parse_from_rfc3339(value).ok().map(TaskTime::Stamp))
+}
+
+// When both times are timestamps the task may span days; otherwise it runs on its own date.
+// Times stored before a snooze or recurrence moved the date fall back to their time of day.
+fn scheduled_range(date: NaiveDate, start: TaskTime, end: TaskTime) -> (NaiveDateTime, NaiveDateTime) {
+    match (start, end) {
+        (TaskTime::Stamp(_), TaskTime::Stamp(_)) => (start.on(date), end.on(date)),
+        _ => (date.and_time(start.time_of_day()), date.and_time(end.time_of_day())),
+    }
+}
+
+fn checked_time(name: &str, value: Option<String>) -> Result<Option<(String, TaskTime)>, String> {
+    let Some(value) = value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    match parse_time(&value) {
+        Some(time) => Ok(Some((value, time))),
+        None => Err(format!("Invalid {}, expected HH:MM or an RFC3339 timestamp: {}", name, value)),
+    }
+}
+
+// Blank times are stored as no time; when both are given the end must be after the start.
+// Two timestamps are compared as they are, otherwise a timestamp must fall on the task's date.
+fn validate_times(
+    date: NaiveDate,
+    start: Option<String>,
+    end: Option<String>,
+) -> Result<(Option<String>, Option<String>), String> {
+    let start = checked_time("start_time", start)?;
+    let end = checked_time("end_time", end)?;
+    match (&start, &end) {
+        (Some((_, TaskTime::Stamp(start))), Some((_, TaskTime::Stamp(end)))) => {
+            if end <= start {
+                return Err("end_time must be after start_time".to_string());
+            }
+        }
+        _ => {
+            for (name, time) in [("start_time", &start), ("end_time", &end)] {
+                if let Some((value, TaskTime::Stamp(stamp))) = time {
+                    if stamp.date_naive() != date {
+                        return Err(format!("{} {} is not on the task's date {}", name, value, date));
+                    }
+                }
+            }
+            if let (Some((_, start)), Some((_, end))) = (&start, &end) {
+                if end.on(date) <= start.on(date) {
+                    return Err("end_time must be after start_time".to_string());
+                }
+            }
+        }
+    }
+    Ok((start.map(|(value, _)| value), end.map(|(value, _)| value)))
+}
+
+// Lowercase without accents, so "Café" and "cafe" compare equal
+fn folded_title(title: &str) -> String {
+    title.nfd().filter(|c| !is_combining_mark(*c)).collect::<String>().to_lowercase()
+}
+
+// A blank description is stored as no description
+fn trimmed_description(description: Option<String>) -> Option<String> {
+    description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty())
+}
+
+fn validate_comment(comment: &Comment) -> Result<(), String> {
+    if comment.content.chars().count() > MAX_COMMENT_LENGTH {
+        return Err(format!("Comment content must be at most {} characters", MAX_COMMENT_LENGTH));
+    }
+    Ok(())
+}
+
+// Checks and normalizes an /import payload like the create handlers would its records.
+// Ids are only kept when replacing, so then they must be unique and not taken by an archived task.
+fn checked_import(
+    mut import: ExportData,
+    merge: bool,
+    archived_ids: &HashSet<u32>,
+) -> Result<ExportData, String> {
+    let mut task_ids = HashSet::new();
+    for (i, task) in import.tasks.iter_mut().enumerate() {
+        let at = |e: String| format!("tasks[{}]: {}", i, e);
+        task.title = trimmed_title(&task.title, "Task").map_err(at)?;
+        let Some(date) = parse_date(task.date.trim()) else {
+            return Err(at(format!("Invalid date, expected YYYY-MM-DD: {}", task.date)));
+        };
+        task.date = date.to_string();
+        (task.start_time, task.end_time) = validate_times(date, task.start_time.take(), task.end_time.take()).map_err(at)?;
+        task.description = trimmed_description(task.description.take());
+        task.tags = normalize_tags(&task.tags);
+        if let (false, Some(id)) = (merge, task.id) {
+            if archived_ids.contains(&id) {
+                return Err(at(format!("id {} belongs to an archived task", id)));
+            }
+            if !task_ids.insert(id) {
+                return Err(at(format!("id {} is used by more than one task", id)));
+            }
+        }
+    }
+
+    let mut comment_ids = HashSet::new();
+    for (i, comment) in import.comments.iter_mut().enumerate() {
+        let at = |e: String| format!("comments[{}]: {}", i, e);
+        validate_comment(comment).map_err(at)?;
+        comment.title = trimmed_title(&comment.title, "Comment").map_err(at)?;
+        if let (false, Some(id)) = (merge, comment.id) {
+            if !comment_ids.insert(id) {
+                return Err(at(format!("id {} is used by more than one comment", id)));
+            }
+        }
+    }
+
+    for (i, goal) in import.goals.iter_mut().enumerate() {
+        let at = |e: String| format!("goals[{}]: {}", i, e);
+        goal.title = trimmed_title(&goal.title, "Goal").map_err(at)?;
+        let Some(due_date) = parse_date(goal.due_date.trim()) else {
+            return Err(at(format!("Invalid due_date, expected YYYY-MM-DD: {}", goal.due_date)));
+        };
+        goal.due_date = due_date.to_string();
+        if goal.progress > MAX_PROGRESS || goal.sub_goals.iter().any(|s| s.progress > MAX_PROGRESS) {
+            return Err(at(format!("progress must be between 0 and {}", MAX_PROGRESS)));
+        }
+    }
+    Ok(import)
+}
+
+// 0-100: the share of progress still missing, divided by the days left plus one,
+// so an untouched goal due today (or overdue) scores 100. Finished goals and
+// goals without a parseable due date score 0.
+fn urgency(goal: &Goal, today: NaiveDate) -> f64 {
+    let Some(due) = parse_date(&goal.due_date) else {
+        return 0.0;
+    };
+    let remaining = MAX_PROGRESS.saturating_sub(goal.progress) as f64 / MAX_PROGRESS as f64;
+    let days_left = (due - today).num_days().max(0);
+    remaining * 100.0 / (days_left + 1) as f64
+}
+
+// Goal due dates are YYYY-MM-DD and, unless `allow_past`, no earlier than today
+fn checked_due_date(due_date: &str, allow_past: bool) -> Result<NaiveDate, String> {
+    let Some(date) = parse_date(due_date.trim()) else {
+        return Err(format!("Invalid due_date, expected YYYY-MM-DD: {}", due_date));
+    };
+    if !allow_past && date < Local::now().date_naive() {
+        return Err(format!("due_date must not be in the past: {}", date));
+    }
+    Ok(date)
+}
+
+// Progress follows the share of completed sub-goals; goals without any keep their manual progress
+fn recompute_progress(goal: &mut Goal) {
+    if !goal.sub_goals.is_empty() {
+        let completed = goal.sub_goals.iter().filter(|s| s.completed).count();
+        goal.progress = (completed * MAX_PROGRESS as usize / goal.sub_goals.len()) as u8;
+    }
+}
+
+// What a fresh install starts with: nothing, or with `sample_comments` (SEED_DATA) a couple
+// of comments, which belong to the default user
+fn seed_data(sample_comments: bool) -> persistence::StoredData {
+    if !sample_comments {
+        return persistence::StoredData { next_task_id: 1, next_comment_id: 1, ..Default::default() };
+    }
+    persistence::StoredData {
+        next_task_id: 1,
+        next_comment_id: 3,
+        comments: vec![
+            Comment {
+                id: Some(1),
+                title: "Market research".to_string(),
+                content: "Find my keynote attached...".to_string(),
+                version: Some(1),
+                created_at: Local::now().to_rfc3339(),
+            },
+            Comment {
+                id: Some(2),
+                title: "Market research".to_string(),
+                content: "I've added the data...".to_string(),
+                version: Some(1),
+                created_at: Local::now().to_rfc3339(),
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+fn music_url(base_url: &str, file: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), file)
+}
+
+// Quotes a CSV field when it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Stored dates use the YYYY-MM-DD form produced by `normalize_date`
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+// Main application routes
+fn today_response() -> DateResponse {
+    let now = Local::now();
+    DateResponse {
+        day: now.day(),
+        month: now.month(),
+        year: now.year(),
+    }
+}
+
+#[get("/current-date")]
+async fn current_date() -> impl Responder {
+    HttpResponse::Ok().json(today_response())
+}
+
+// Server-sent events: the current date as a `data:` JSON line on connect and then once a minute
+#[get("/current-date/stream")]
+async fn current_date_stream() -> impl Responder {
+    let ticks = actix_web::rt::time::interval(std::time::Duration::from_secs(60));
+    let events = stream::unfold(ticks, |mut ticks| async move {
+        ticks.tick().await;
+        let event = serde_json::to_string(&today_response())
+            .map(|json| web::Bytes::from(format!("data: {}\n\n", json)));
+        Some((event, ticks))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+// Deliberately lock-free so it keeps answering even if a handler has poisoned a mutex
+#[get("/health")]
+async fn health(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(HealthResponse {
+        status: "ok",
+        uptime_secs: data.started_at.elapsed().as_secs(),
+    })
+}
+
+// Prometheus text format: collection sizes as gauges plus request counts from metrics::track_requests
+#[get("/metrics")]
+async fn get_metrics(
+    data: web::Data<AppState>,
+    bot_data: web::Data<BotAppState>,
+    request_metrics: web::Data<metrics::RequestMetrics>
+) -> impl Responder {
+    let users: Vec<Arc<UserData>> = lock(&data.users).values().cloned().collect();
+    let total = |len: fn(&UserData) -> usize| users.iter().map(|user| len(user)).sum::<usize>();
+    let gauges = [
+        ("tasks_total", "Tasks currently stored, across all users.", total(|user| lock(&user.tasks).len())),
+        ("goals_total", "Goals currently stored, across all users.", total(|user| lock(&user.goals).len())),
+        ("comments_total", "Comments currently stored, across all users.", total(|user| lock(&user.comments).len())),
+        ("bot_tasks_total", "Bot tasks currently stored.", lock(&bot_data.tasks).len()),
+        ("bot_goals_total", "Bot goals currently stored.", lock(&bot_data.goals).len()),
+    ];
+    let mut body = String::new();
+    for (name, help, value) in gauges {
+        body.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n{} {}\n", name, help, name, name, value));
+    }
+    request_metrics.render(&mut body);
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body)
+}
+
+// Accepts optional `completed`, `priority`, `date` and `tag` query params,
+// e.g. /tasks?completed=true&priority=high&date=2024-06-01&tag=work
+// `sort` may be `date`, `priority`, `title`, `created_at` or `smart`; without it tasks keep insertion order.
+// `smart` orders High -> Medium -> Low, incomplete before completed, then by date.
+// `page` and `per_page` select a slice of the result (default 1 and 20, max 100 per page)
+// Sends the matching tasks one JSON object per line when the client accepts
+// `application/x-ndjson`; pagination doesn't apply to the stream.
+// The JSON response carries an ETag so unchanged pages can be answered with 304.
+#[get("/tasks")]
+async fn get_tasks(
+    req: HttpRequest,
+    query: web::Query<TaskFilter>,
+    pagination: web::Query<Pagination>,
+    user: User,
+) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let mut filtered: Vec<Task> = tasks
+        .iter()
+        .filter(|t| !t.deleted)
+        .filter(|t| query.completed.is_none_or(|c| t.completed == c))
+        .filter(|t| query.priority.is_none_or(|p| t.priority == p))
+        .filter(|t| query.date.as_ref().is_none_or(|d| &t.date == d))
+        .filter(|t| query.tag.as_ref().is_none_or(|tag| t.tags.contains(&normalize_tag(tag))))
+        .cloned()
+        .collect();
+
+    match query.sort.as_deref() {
+        // Unparseable dates sort after every real date
+        Some("date") => filtered.sort_by_key(|t| parse_date(&t.date).unwrap_or(NaiveDate::MAX)),
+        Some("priority") => filtered.sort_by_key(|t| std::cmp::Reverse(t.priority)),
+        Some("smart") => filtered.sort_by_key(|t| {
+            (std::cmp::Reverse(t.priority), t.completed, parse_date(&t.date).unwrap_or(NaiveDate::MAX))
+        }),
+        Some("title") => filtered.sort_by(|a, b| a.title.cmp(&b.title)),
+        // Tasks without a parseable timestamp sort last
+        Some("created_at") => filtered.sort_by_key(|t| {
+            let created_at = DateTime::parse_from_rfc3339(&t.created_at).ok();
+            (created_at.is_none(), created_at)
+        }),
+        Some(other) => {
+            return ApiError::bad_request(format!("Unknown sort key: {}", other));
+        }
+        None => {}
+    }
+
+    if accepts_ndjson(&req) {
+        let lines = stream::iter(filtered).map(|task| {
+            let mut line = serde_json::to_vec(&task)?;
+            line.push(b'\n');
+            Ok::<_, serde_json::Error>(web::Bytes::from(line))
+        });
+        return HttpResponse::Ok().content_type("application/x-ndjson").streaming(lines);
+    }
+
+    let body = match serde_json::to_vec(&paginate(filtered, &pagination)) {
+        Ok(body) => body,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(ApiError { error: "internal", detail: e.to_string() });
+        }
+    };
+    let etag = etag_for(&body);
+    if if_none_match(&req, &etag) {
+        return HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish();
+    }
+    HttpResponse::Ok()
+        .insert_header((header::ETAG, etag))
+        .content_type("application/json")
+        .body(body)
+}
+
+#[get("/tasks/today")]
+async fn get_tasks_today(user: User) -> impl Responder {
+    let today = Local::now().date_naive();
+    let tasks = lock(&user.tasks);
+    let due_today: Vec<Task> = tasks
+        .iter()
+        .filter(|t| !t.deleted && parse_date(&t.date) == Some(today))
+        .cloned()
+        .collect();
+    HttpResponse::Ok().json(due_today)
+}
+
+// Every task keyed by its date, for rendering one column per day
+#[get("/tasks/by-date")]
+async fn get_tasks_by_date(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let mut by_date: HashMap<String, Vec<Task>> = HashMap::new();
+    for task in tasks.iter().filter(|t| !t.deleted) {
+        by_date.entry(task.date.clone()).or_default().push(task.clone());
+    }
+    HttpResponse::Ok().json(by_date)
+}
+
+// Incomplete tasks dated from today through `days` days ahead (default 7), soonest first
+#[get("/tasks/upcoming")]
+async fn get_upcoming_tasks(query: web::Query<UpcomingQuery>, user: User) -> impl Responder {
+    let today = Local::now().date_naive();
+    let until = today
+        .checked_add_signed(chrono::Duration::days(query.days.unwrap_or(7).into()))
+        .unwrap_or(NaiveDate::MAX);
+    let tasks = lock(&user.tasks);
+    let mut upcoming: Vec<(NaiveDate, Task)> = tasks
+        .iter()
+        .filter(|t| !t.completed && !t.deleted)
+        .filter_map(|t| parse_date(&t.date).map(|d| (d, t.clone())))
+        .filter(|(d, _)| *d >= today && *d <= until)
+        .collect();
+    upcoming.sort_by_key(|(d, _)| *d);
+    HttpResponse::Ok().json(upcoming.into_iter().map(|(_, t)| t).collect::<Vec<_>>())
+}
+
+// Completed tasks dated from `from` through `to` (inclusive, YYYY-MM-DD), earliest first
+#[get("/tasks/completed")]
+async fn get_completed_tasks(query: web::Query<DateRange>, user: User) -> impl Responder {
+    let (Some(from), Some(to)) = (parse_date(&query.from), parse_date(&query.to)) else {
+        return ApiError::bad_request("from and to must be dates in YYYY-MM-DD form");
+    };
+    if from > to {
+        return ApiError::bad_request("from must not be after to");
+    }
+    let tasks = lock(&user.tasks);
+    let mut completed: Vec<(NaiveDate, Task)> = tasks
+        .iter()
+        .filter(|t| t.completed && !t.deleted)
+        .filter_map(|t| parse_date(&t.date).map(|d| (d, t.clone())))
+        .filter(|(d, _)| *d >= from && *d <= to)
+        .collect();
+    completed.sort_by_key(|(d, _)| *d);
+    HttpResponse::Ok().json(completed.into_iter().map(|(_, t)| t).collect::<Vec<_>>())
+}
+
+#[get("/tasks/archived")]
+async fn get_archived_tasks(user: User) -> impl Responder {
+    HttpResponse::Ok().json(lock(&user.archived_tasks).clone())
+}
+
+// Moves completed tasks dated more than `days` days ago (default 30) to the archive.
+// Tasks in the trash or with an unparseable date are left alone.
+#[post("/tasks/archive")]
+async fn archive_tasks(query: web::Query<ArchiveQuery>, user: User) -> impl Responder {
+    let cutoff = Local::now()
+        .date_naive()
+        .checked_sub_signed(chrono::Duration::days(query.days.unwrap_or(30).into()))
+        .unwrap_or(NaiveDate::MIN);
+    let _writing = user.writes.lock().await;
+    let old = {
+        let mut tasks = lock(&user.tasks);
+        let (old, kept): (Vec<Task>, Vec<Task>) = tasks.drain(..).partition(|t| {
+            t.completed && !t.deleted && parse_date(&t.date).is_some_and(|d| d < cutoff)
+        });
+        *tasks = kept;
+        old
+    };
+
+    let archived = old.len();
+    let archived_ids: Vec<u32> = old.iter().filter_map(|t| t.id).collect();
+    lock(&user.archived_tasks).extend(old);
+    if archived > 0 && persistence::archive_tasks(&user, &archived_ids).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    for id in archived_ids {
+        user.audit.record("task", "archive", id);
+        user.task_updates.broadcast(&TaskEvent::Deleted { id });
+    }
+    HttpResponse::Ok().json(ArchivedCount { archived })
+}
+
+#[get("/tasks/export.csv")]
+async fn export_tasks_csv(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let mut csv = String::from("id,title,date,completed,priority\n");
+    for task in tasks.iter().filter(|t| !t.deleted) {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            task.id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(&task.title),
+            csv_field(&task.date),
+            task.completed,
+            task.priority.as_str(),
+        ));
+    }
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"tasks.csv\""))
+        .body(csv)
+}
+
+// Pairs of tasks whose start_time..end_time ranges overlap, in order of the first one's start.
+// Tasks without both times are ignored; ranges that only touch don't count.
+#[get("/tasks/conflicts")]
+async fn get_task_conflicts(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let mut scheduled: Vec<(NaiveDateTime, NaiveDateTime, &Task)> = Vec::new();
+    for task in tasks.iter().filter(|t| !t.deleted) {
+        let date = parse_date(&task.date);
+        let start = task.start_time.as_deref().and_then(parse_time);
+        let end = task.end_time.as_deref().and_then(parse_time);
+        if let (Some(date), Some(start), Some(end)) = (date, start, end) {
+            let (start, end) = scheduled_range(date, start, end);
+            scheduled.push((start, end, task));
+        }
+    }
+    scheduled.sort_by_key(|(start, _, _)| *start);
+
+    let mut conflicts = Vec::new();
+    for (i, (start_a, end_a, a)) in scheduled.iter().enumerate() {
+        // Sorted by start, so once a task starts after `a` has ended none of the later ones overlap it
+        for (_, end_b, b) in scheduled[i + 1..].iter().take_while(|(start_b, _, _)| start_b < end_a) {
+            if start_a < end_b {
+                conflicts.push(TaskConflict { first: (*a).clone(), second: (*b).clone() });
+            }
+        }
+    }
+    HttpResponse::Ok().json(conflicts)
+}
+
+// Consecutive days with at least one completed task, going by task dates and including archived tasks.
+// The current streak is still running if the last such day was yesterday.
+#[get("/tasks/streak")]
+async fn get_task_streak(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let archived = lock(&user.archived_tasks);
+    let days: BTreeSet<NaiveDate> = tasks
+        .iter()
+        .filter(|t| !t.deleted)
+        .chain(archived.iter())
+        .filter(|t| t.completed)
+        .filter_map(|t| parse_date(&t.date))
+        .collect();
+    drop(archived);
+    drop(tasks);
+
+    let mut longest_streak = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for &day in &days {
+        run = if previous.and_then(|p| p.succ_opt()) == Some(day) { run + 1 } else { 1 };
+        longest_streak = longest_streak.max(run);
+        previous = Some(day);
+    }
+
+    let today = Local::now().date_naive();
+    let mut day = if days.contains(&today) { Some(today) } else { today.pred_opt() };
+    let mut current_streak = 0;
+    while let Some(d) = day.filter(|d| days.contains(d)) {
+        current_streak += 1;
+        day = d.pred_opt();
+    }
+    HttpResponse::Ok().json(Streak { current_streak, longest_streak })
+}
+
+// Every tag in use with the number of tasks carrying it, sorted by tag
+#[get("/tasks/tags")]
+async fn get_task_tags(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for tag in tasks.iter().filter(|t| !t.deleted).flat_map(|t| &t.tags) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    let mut tags: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag: tag.to_string(), count })
+        .collect();
+    tags.sort_by(|a, b| a.tag.cmp(&b.tag));
+    HttpResponse::Ok().json(tags)
+}
+
+#[get("/tasks/stats")]
+async fn get_task_stats(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let mut stats = TaskStats::default();
+    for task in tasks.iter().filter(|t| !t.deleted) {
+        stats.total += 1;
+        if task.completed {
+            stats.completed += 1;
+        } else {
+            stats.pending += 1;
+        }
+        match task.priority {
+            Priority::Low => stats.by_priority.low += 1,
+            Priority::Medium => stats.by_priority.medium += 1,
+            Priority::High => stats.by_priority.high += 1,
+        }
+    }
+    HttpResponse::Ok().json(stats)
+}
+
+#[get("/tasks/trash")]
+async fn get_trashed_tasks(user: User) -> impl Responder {
+    let tasks = lock(&user.tasks);
+    let trashed: Vec<Task> = tasks.iter().filter(|t| t.deleted).cloned().collect();
+    HttpResponse::Ok().json(trashed)
+}
+
+#[get("/tasks/{id}")]
+async fn get_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let task_id = task_id.into_inner();
+    let tasks = lock(&user.tasks);
+    match tasks.iter().find(|task| task.id == Some(task_id) && !task.deleted) {
+        Some(task) => HttpResponse::Ok().json(task),
+        None => ApiError::not_found("Task not found"),
+    }
+}
+
+// With ?dedup=true a task whose title matches an existing one on the same date (ignoring case and accents)
+// isn't added; the existing task comes back with 409 instead.
+// Repeating an `Idempotency-Key` header within IDEMPOTENCY_WINDOW returns the task the
+// first request created instead of adding another one.
+#[post("/tasks")]
+async fn add_task(
+    req: HttpRequest,
+    query: web::Query<AddTaskQuery>,
+    task: web::Json<Task>,
+    user: User,
+) -> impl Responder {
+    log::debug!("Received task: {:?}", task);
+    let mut new_task = task.into_inner();
+    new_task.title = match trimmed_title(&new_task.title, "Task") {
+        Ok(title) => title,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let Some(date) = normalize_date(&new_task.date) else {
+        return ApiError::bad_request(format!("Invalid date, expected YYYY-MM-DD: {}", new_task.date));
+    };
+    new_task.date = date.to_string();
+    (new_task.start_time, new_task.end_time) = match validate_times(date, new_task.start_time, new_task.end_time) {
+        Ok(times) => times,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    new_task.description = trimmed_description(new_task.description);
+    new_task.tags = normalize_tags(&new_task.tags);
+    new_task.created_at = Local::now().to_rfc3339();
+    new_task.updated_at = new_task.created_at.clone();
+    new_task.deleted = false;
+    let idempotency_key = req
+        .headers()
+        .get("Idempotency-Key")
+        .and_then(|value| value.to_str().ok())
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty());
+
+    let _writing = user.writes.lock().await;
+    let task_id = {
+        let mut tasks = lock(&user.tasks);
+        let mut keys = lock(&user.idempotency_keys);
+        keys.retain(|_, (_, seen_at)| seen_at.elapsed() < IDEMPOTENCY_WINDOW);
+        if let Some((task_id, _)) = idempotency_key.as_ref().and_then(|key| keys.get(key)) {
+            if let Some(original) = tasks.iter().find(|t| t.id == Some(*task_id) && !t.deleted) {
+                return HttpResponse::Ok().json(original);
+            }
+        }
+        if query.dedup.unwrap_or(false) {
+            let title = folded_title(&new_task.title);
+            let existing = tasks
+                .iter()
+                .find(|t| !t.deleted && t.date == new_task.date && folded_title(&t.title) == title);
+            if let Some(existing) = existing {
+                return HttpResponse::Conflict().json(existing);
+            }
+        }
+        let task_id = user.next_task_id.fetch_add(1, Ordering::SeqCst);
+        new_task.id = Some(task_id);
+        if let Some(key) = idempotency_key {
+            keys.insert(key, (task_id, Instant::now()));
+        }
+        tasks.push(new_task.clone());
+        task_id
+    };
+    if persistence::insert_tasks(&user, std::slice::from_ref(&new_task)).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "create", task_id);
+    user.task_updates.broadcast(&TaskEvent::Added { task: new_task.clone() });
+    HttpResponse::Ok().json(new_task)
+}
+
+#[post("/tasks/complete/{id}")]
+async fn complete_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        task.completed = true;
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "complete", task_id);
+    user.task_updates.broadcast(&TaskEvent::Completed { task: updated_task.clone() });
+    HttpResponse::Ok().json(updated_task)
+}
+
+#[post("/tasks/complete-batch")]
+async fn complete_tasks_batch(ids: web::Json<Vec<u32>>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let now = Local::now().to_rfc3339();
+    let mut result = BatchCompleteResult { updated: vec![], not_found: vec![] };
+    let mut completed_tasks = Vec::new();
+    {
+        let mut tasks = lock(&user.tasks);
+        for id in ids.into_inner() {
+            if let Some(task) = tasks.iter_mut().find(|task| task.id == Some(id) && !task.deleted) {
+                task.completed = true;
+                task.updated_at = now.clone();
+                completed_tasks.push(task.clone());
+                result.updated.push(id);
+            } else {
+                result.not_found.push(id);
+            }
+        }
+    }
+
+    if persistence::save_tasks(&user, &completed_tasks).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    for (id, task) in result.updated.iter().zip(completed_tasks) {
+        user.audit.record("task", "complete", id);
+        user.task_updates.broadcast(&TaskEvent::Completed { task });
+    }
+    HttpResponse::Ok().json(result)
+}
+
+#[post("/tasks/incomplete/{id}")]
+async fn incomplete_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        task.completed = false;
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "update", task_id);
+    user.task_updates.broadcast(&TaskEvent::Updated { task: updated_task.clone() });
+    HttpResponse::Ok().json(updated_task)
+}
+
+#[post("/tasks/{id}/toggle")]
+async fn toggle_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        task.completed = !task.completed;
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "update", task_id);
+    let event = if updated_task.completed {
+        TaskEvent::Completed { task: updated_task.clone() }
+    } else {
+        TaskEvent::Updated { task: updated_task.clone() }
+    };
+    user.task_updates.broadcast(&event);
+    HttpResponse::Ok().json(updated_task)
+}
+
+// Completes a recurring task and schedules its next instance.
+// An already completed task gets 409, so its next instance isn't scheduled twice.
+#[post("/tasks/{id}/complete-recurring")]
+async fn complete_recurring_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let (completed, next_id, next) = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        let Some(recurrence) = task.recurrence else {
+            return ApiError::bad_request("Task has no recurrence");
+        };
+        if task.completed {
+            return ApiError::conflict("Task is already completed");
+        }
+        let Some(next_date) = parse_date(&task.date).and_then(|d| next_occurrence(d, recurrence)) else {
+            return ApiError::bad_request(format!("Task date is not a valid date: {}", task.date));
+        };
+
+        let now = Local::now().to_rfc3339();
+        task.completed = true;
+        task.updated_at = now.clone();
+        let completed = task.clone();
+        let next_id = user.next_task_id.fetch_add(1, Ordering::SeqCst);
+        let next = Task {
+            id: Some(next_id),
+            date: next_date.to_string(),
+            completed: false,
+            created_at: now.clone(),
+            updated_at: now,
+            ..completed.clone()
+        };
+        tasks.push(next.clone());
+        (completed, next_id, next)
+    };
+    if persistence::insert_tasks(&user, &[completed.clone(), next.clone()]).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "complete", task_id);
+    user.audit.record("task", "create", next_id);
+    user.task_updates.broadcast(&TaskEvent::Completed { task: completed.clone() });
+    user.task_updates.broadcast(&TaskEvent::Added { task: next.clone() });
+    HttpResponse::Ok().json(RecurringCompletion { completed, next })
+}
+
+// Pushes the task's date out by `days` (at least 1)
+#[post("/tasks/{id}/snooze")]
+async fn snooze_task(
+    task_id: web::Path<u32>,
+    body: web::Json<SnoozeTask>,
+    user: User,
+) -> impl Responder {
+    if body.days < 1 {
+        return ApiError::bad_request("days must be a positive number");
+    }
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        let Some(date) = parse_date(&task.date) else {
+            return ApiError::bad_request(format!("Task date is not a valid date: {}", task.date));
+        };
+        let Some(snoozed) = chrono::Duration::try_days(body.days).and_then(|days| date.checked_add_signed(days)) else {
+            return ApiError::bad_request("days moves the task past the latest supported date");
+        };
+
+        task.date = snoozed.to_string();
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "update", task_id);
+    user.task_updates.broadcast(&TaskEvent::Updated { task: updated_task.clone() });
+    HttpResponse::Ok().json(updated_task)
+}
+
+#[post("/tasks/{id}/duplicate")]
+async fn duplicate_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let (copy_id, copy) = {
+        let mut tasks = lock(&user.tasks);
+        let Some(source) = tasks.iter().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+
+        let now = Local::now().to_rfc3339();
+        let copy_id = user.next_task_id.fetch_add(1, Ordering::SeqCst);
+        let copy = Task {
+            id: Some(copy_id),
+            title: format!("{} (copy)", source.title),
+            completed: false,
+            created_at: now.clone(),
+            updated_at: now,
+            ..source.clone()
+        };
+        tasks.push(copy.clone());
+        (copy_id, copy)
+    };
+    if persistence::insert_tasks(&user, std::slice::from_ref(&copy)).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "create", copy_id);
+    user.task_updates.broadcast(&TaskEvent::Added { task: copy.clone() });
+    HttpResponse::Ok().json(copy)
+}
+
+#[put("/tasks/{id}")]
+async fn update_task(
+    path: web::Path<u32>,
+    task: web::Json<Task>,
+    user: User,
+) -> impl Responder {
+    let id = path.into_inner();
+    let task = task.into_inner();
+    let title = match trimmed_title(&task.title, "Task") {
+        Ok(title) => title,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let Some(date) = normalize_date(&task.date) else {
+        return ApiError::bad_request(format!("Invalid date, expected YYYY-MM-DD: {}", task.date));
+    };
+    let (start_time, end_time) = match validate_times(date, task.start_time, task.end_time) {
+        Ok(times) => times,
+        Err(e) => return ApiError::bad_request(e),
+    };
+
+    let _writing = user.writes.lock().await;
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(existing_task) = tasks.iter_mut().find(|t| t.id == Some(id) && !t.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        existing_task.title = title;
+        existing_task.description = trimmed_description(task.description);
+        existing_task.date = date.to_string();
+        existing_task.start_time = start_time;
+        existing_task.end_time = end_time;
+        existing_task.priority = task.priority;
+        existing_task.completed = task.completed;
+        existing_task.recurrence = task.recurrence;
+        existing_task.updated_at = Local::now().to_rfc3339();
+        existing_task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "update", id);
+    user.task_updates.broadcast(&TaskEvent::Updated { task: updated_task.clone() });
+    HttpResponse::Ok().json(updated_task)
+}
+
+#[post("/tasks/{id}/tags")]
+async fn add_task_tag(
+    path: web::Path<u32>,
+    body: web::Json<AddTag>,
+    user: User,
+) -> impl Responder {
+    let id = path.into_inner();
+    let tag = normalize_tag(&body.tag);
+    if tag.is_empty() {
+        return ApiError::bad_request("Tag must not be empty");
+    }
+
+    let _writing = user.writes.lock().await;
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|t| t.id == Some(id) && !t.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        if !task.tags.contains(&tag) {
+            task.tags.push(tag);
+            task.updated_at = Local::now().to_rfc3339();
+        }
+        task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "update", id);
+    user.task_updates.broadcast(&TaskEvent::Updated { task: updated_task.clone() });
+    HttpResponse::Ok().json(updated_task)
+}
+
+#[delete("/tasks/{id}/tags/{tag}")]
+async fn remove_task_tag(path: web::Path<(u32, String)>, user: User) -> impl Responder {
+    let (id, tag) = path.into_inner();
+    let tag = normalize_tag(&tag);
+    let _writing = user.writes.lock().await;
+    let updated_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|t| t.id == Some(id) && !t.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        if !task.tags.contains(&tag) {
+            return ApiError::not_found("Tag not found on task");
+        }
+        task.tags.retain(|t| t != &tag);
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "update", id);
+    user.task_updates.broadcast(&TaskEvent::Updated { task: updated_task.clone() });
+    HttpResponse::Ok().json(updated_task)
+}
+
+// Must be registered before `delete_task` so "completed" isn't taken as an id.
+// Cleared tasks go to the trash like a single delete.
+#[delete("/tasks/completed")]
+async fn clear_completed_tasks(user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let removed_tasks: Vec<Task> = {
+        let mut tasks = lock(&user.tasks);
+        let now = Local::now().to_rfc3339();
+        let mut removed_tasks = Vec::new();
+        for task in tasks.iter_mut().filter(|task| task.completed && !task.deleted) {
+            task.deleted = true;
+            task.updated_at = now.clone();
+            removed_tasks.push(task.clone());
+        }
+        removed_tasks
+    };
+    if persistence::save_tasks(&user, &removed_tasks).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    for id in removed_tasks.iter().filter_map(|task| task.id) {
+        user.audit.record("task", "delete", id);
+        user.task_updates.broadcast(&TaskEvent::Deleted { id });
+    }
+    HttpResponse::Ok().json(RemovedCount { removed: removed_tasks.len() })
+}
+
+// Soft delete: the task moves to the trash and can be brought back with /tasks/{id}/restore
+#[delete("/tasks/{id}")]
+async fn delete_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let deleted_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && !task.deleted) else {
+            return ApiError::not_found("Task not found");
+        };
+        task.deleted = true;
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &deleted_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "delete", task_id);
+    user.task_updates.broadcast(&TaskEvent::Deleted { id: task_id });
+    HttpResponse::Ok().finish()
+}
+
+#[post("/tasks/{id}/restore")]
+async fn restore_task(task_id: web::Path<u32>, user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let restored_task = {
+        let mut tasks = lock(&user.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id) && task.deleted) else {
+            return ApiError::not_found("Task not found in trash");
+        };
+        task.deleted = false;
+        task.updated_at = Local::now().to_rfc3339();
+        task.clone()
+    };
+    if persistence::save_task(&user, &restored_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("task", "restore", task_id);
+    user.task_updates.broadcast(&TaskEvent::Added { task: restored_task.clone() });
+    HttpResponse::Ok().json(restored_task)
+}
+
+// Permanently removes every trashed task. Must be registered before `delete_task`.
+#[delete("/tasks/trash")]
+async fn empty_trash(user: User) -> impl Responder {
+    let _writing = user.writes.lock().await;
+    let purged_ids: Vec<u32> = {
+        let mut tasks = lock(&user.tasks);
+        let purged_ids = tasks.iter().filter(|task| task.deleted).filter_map(|task| task.id).collect();
+        tasks.retain(|task| !task.deleted);
+        purged_ids
+    };
+    if persistence::delete_tasks(&user, &purged_ids).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    for &id in &purged_ids {
+        user.audit.record("task", "purge", id);
+    }
+    HttpResponse::Ok().json(RemovedCount { removed: purged_ids.len() })
+}
+
+// Clients receive a TaskEvent message whenever a task is added, updated, completed or deleted
+#[get("/ws/tasks")]
+async fn task_updates_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    user: User,
+    data: web::Data<AppState>
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    // The upgrade is a GET, which doesn't register the user, but the broadcaster
+    // has to be the one their later writes go through
+    user.register(&data).task_updates.subscribe(session, stream);
+    Ok(response)
+}
+
+// Clients receive the full goal JSON whenever a goal is edited, its progress changes or its sub-goals change
+#[get("/ws/goals")]
+async fn goal_updates_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    user: User,
+    data: web::Data<AppState>
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    user.register(&data).goal_updates.subscribe(session, stream);
+    Ok(response)
+}
+
+// `title` matches the whole title exactly, `title_contains` a case-insensitive substring.
+// `order` is `newest` (the default) or `oldest`, by created_at and then id;
+// comments without a timestamp count as the oldest.
+// `page` and `per_page` select a slice of the result (default 1 and 20, max 100 per page)
+#[get("/comments")]
+async fn get_comments(
+    query: web::Query<CommentFilter>,
+    pagination: web::Query<Pagination>,
+    user: User,
+) -> impl Responder {
+    let comments = lock(&user.comments);
+    let contains = query.title_contains.as_ref().map(|term| term.to_lowercase());
+    let mut filtered: Vec<Comment> = comments
+        .iter()
+        .filter(|c| query.title.as_ref().is_none_or(|title| &c.title == title))
+        .filter(|c| contains.as_ref().is_none_or(|term| c.title.to_lowercase().contains(term)))
+        .cloned()
+        .collect();
+    let age_key = |c: &Comment| (DateTime::parse_from_rfc3339(&c.created_at).ok(), c.id);
+    match query.order.as_deref() {
+        None | Some("newest") => filtered.sort_by_key(|c| std::cmp::Reverse(age_key(c))),
+        Some("oldest") => filtered.sort_by_key(age_key),
+        Some(other) => return ApiError::bad_request(format!("Unknown order: {}", other)),
+    }
+    HttpResponse::Ok().json(paginate(filtered, &pagination))
+}
+
+#[get("/comments/count")]
+async fn get_comment_count(user: User) -> impl Responder {
+    let count = lock(&user.comments).len();
+    HttpResponse::Ok().json(CountResponse { count })
+}
+
+#[post("/comments")]
+async fn add_comment(comment: web::Json<Comment>, user: User) -> impl Responder {
+    let mut new_comment = comment.into_inner();
+    if let Err(e) = validate_comment(&new_comment) {
+        return ApiError::bad_request(e);
+    }
+    new_comment.title = match trimmed_title(&new_comment.title, "Comment") {
+        Ok(title) => title,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let _writing = user.writes.lock().await;
+    let comment_id = {
+        let mut comments = lock(&user.comments);
+        let comment_id = user.next_comment_id.fetch_add(1, Ordering::SeqCst);
+        new_comment.id = Some(comment_id);
+        new_comment.version = Some(1);
+        new_comment.created_at = Local::now().to_rfc3339();
+        comments.push(new_comment.clone());
+        comment_id
+    };
+    if persistence::insert_comment(&user, &new_comment).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("comment", "create", comment_id);
+    HttpResponse::Ok().json(new_comment)
+}
+
+#[get("/comments/{id}")]
+async fn get_comment(path: web::Path<u32>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    let comments = lock(&user.comments);
+    match comments.iter().find(|c| c.id == Some(id)) {
+        Some(comment) => HttpResponse::Ok().json(comment),
+        None => ApiError::not_found("Comment not found"),
+    }
+}
+
+#[put("/comments/{id}")]
+async fn update_comment(
+    path: web::Path<u32>,
+    comment: web::Json<Comment>,
+    user: User,
+) -> impl Responder {
+    let id = path.into_inner();
+    let mut comment = comment.into_inner();
+    if let Err(e) = validate_comment(&comment) {
+        return ApiError::bad_request(e);
+    }
+    comment.title = match trimmed_title(&comment.title, "Comment") {
+        Ok(title) => title,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let _writing = user.writes.lock().await;
+    let updated_comment = {
+        let mut comments = lock(&user.comments);
+        let Some(existing_comment) = comments.iter_mut().find(|c| c.id == Some(id)) else {
+            return ApiError::not_found("Comment not found");
+        };
+        // Comments stored before versioning count as version 0
+        let current_version = existing_comment.version.unwrap_or(0);
+        if comment.version.is_some_and(|v| v != current_version) {
+            return ApiError::conflict(format!(
+                "Comment was modified by someone else, current version is {}",
+                current_version
+            ));
+        }
+        let created_at = std::mem::take(&mut existing_comment.created_at);
+        *existing_comment = comment;
+        existing_comment.id = Some(id);
+        existing_comment.created_at = created_at;
+        existing_comment.version = Some(current_version + 1);
+        existing_comment.clone()
+    };
+    if persistence::save_comment(&user, &updated_comment).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("comment", "update", id);
+    HttpResponse::Ok().json(updated_comment)
+}
+
+#[delete("/comments/{id}")]
+async fn delete_comment(path: web::Path<u32>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = user.writes.lock().await;
+    {
+        let mut comments = lock(&user.comments);
+        if !comments.iter().any(|c| c.id == Some(id)) {
+            return ApiError::not_found("Comment not found");
+        }
+        comments.retain(|c| c.id != Some(id));
+    }
+    if persistence::delete_comment(&user, id).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("comment", "delete", id);
+    HttpResponse::Ok().finish()
+}
+
+// Case-insensitive match on task titles and comment titles/content
+#[get("/search")]
+async fn search(query: web::Query<SearchQuery>, user: User) -> impl Responder {
+    let term = query.q.as_deref().unwrap_or("").trim().to_lowercase();
+    if term.is_empty() {
+        return HttpResponse::Ok().json(SearchResults { tasks: vec![], comments: vec![] });
+    }
+
+    let tasks = lock(&user.tasks)
+        .iter()
+        .filter(|t| !t.deleted && t.title.to_lowercase().contains(&term))
+        .cloned()
+        .collect();
+    let comments = lock(&user.comments)
+        .iter()
+        .filter(|c| c.title.to_lowercase().contains(&term) || c.content.to_lowercase().contains(&term))
+        .cloned()
+        .collect();
+    HttpResponse::Ok().json(SearchResults { tasks, comments })
+}
+
+// `sort=due_date` lists the nearest due date first and `sort=urgency` the most urgent first;
+// without it goals keep insertion order. `min_progress` and `max_progress` are inclusive bounds.
+// Each goal carries an `urgency` score, see `urgency`.
+#[get("/goals")]
+async fn get_goals(
+    query: web::Query<GoalQuery>,
+    pagination: web::Query<Pagination>,
+    user: User,
+) -> impl Responder {
+    if let (Some(min), Some(max)) = (query.min_progress, query.max_progress) {
+        if min > max {
+            return ApiError::bad_request("min_progress must not be greater than max_progress");
+        }
+    }
+
+    let today = Local::now().date_naive();
+    let goals = lock(&user.goals);
+    let mut goals: Vec<RankedGoal> = goals
+        .iter()
+        .filter(|g| query.priority.is_none_or(|p| g.priority == p))
+        .filter(|g| query.min_progress.is_none_or(|min| g.progress >= min))
+        .filter(|g| query.max_progress.is_none_or(|max| g.progress <= max))
+        .map(|g| RankedGoal { goal: g.clone(), urgency: urgency(g, today) })
+        .collect();
+    match query.sort.as_deref() {
+        // Unparseable due dates sort after every real date
+        Some("due_date") => goals.sort_by_key(|g| parse_date(&g.goal.due_date).unwrap_or(NaiveDate::MAX)),
+        Some("urgency") => goals.sort_by(|a, b| b.urgency.total_cmp(&a.urgency)),
+        Some(other) => return ApiError::bad_request(format!("Unknown sort key: {}", other)),
+        None => {}
+    }
+    HttpResponse::Ok().json(paginate(goals, &pagination))
+}
+
+#[get("/goals/{id}")]
+async fn get_goal(path: web::Path<Uuid>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    let goals = lock(&user.goals);
+    match goals.iter().find(|g| g.id == id) {
+        Some(goal) => HttpResponse::Ok().json(goal),
+        None => ApiError::not_found("Goal not found"),
+    }
+}
+
+// Goals past their due date that aren't finished; unparseable due dates are skipped
+#[get("/goals/overdue")]
+async fn get_overdue_goals(user: User) -> impl Responder {
+    let today = Local::now().date_naive();
+    let goals = lock(&user.goals);
+    let overdue: Vec<Goal> = goals
+        .iter()
+        .filter(|g| g.progress < MAX_PROGRESS)
+        .filter(|g| parse_date(&g.due_date).is_some_and(|due| due < today))
+        .cloned()
+        .collect();
+    HttpResponse::Ok().json(overdue)
+}
+
+// Points left per goal (100 - progress) and their sum across all goals
+#[get("/goals/remaining")]
+async fn get_remaining_work(user: User) -> impl Responder {
+    let goals = lock(&user.goals);
+    let goals: Vec<GoalRemaining> = goals
+        .iter()
+        .map(|g| GoalRemaining {
+            id: g.id,
+            title: g.title.clone(),
+            remaining: MAX_PROGRESS.saturating_sub(g.progress),
+        })
+        .collect();
+    let total_remaining = goals.iter().map(|g| g.remaining as u32).sum();
+    HttpResponse::Ok().json(RemainingWork { goals, total_remaining })
+}
+
+// `due_date` must be a YYYY-MM-DD date no earlier than today, unless ?allow_past=true
+#[post("/goals")]
+async fn create_goal(
+    user: User,
+    query: web::Query<AllowPastQuery>,
+    goal: web::Json<CreateGoal>,
+) -> impl Responder {
+    let title = match trimmed_title(&goal.title, "Goal") {
+        Ok(title) => title,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let due_date = match checked_due_date(&goal.due_date, query.allow_past.unwrap_or(false)) {
+        Ok(due_date) => due_date,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let _writing = user.writes.lock().await;
+    let new_goal = Goal {
+        id: Uuid::new_v4(),
+        title,
+        description: goal.description.clone(),
+        priority: goal.priority,
+        due_date: due_date.to_string(),
+        progress: 0,
+        sub_goals: Vec::new(),
+        history: Vec::new(),
+    };
+    lock(&user.goals).push(new_goal.clone());
+    if persistence::save_goal(&user, &new_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "create", new_goal.id);
+    HttpResponse::Ok().json(new_goal)
+}
+
+// Copies a goal and its sub-goals under fresh ids with all progress reset.
+// The body is optional; `due_date` in it replaces the source's due date and is
+// checked like in create_goal, including ?allow_past=true.
+// A request without a Content-Type counts as having no body, anything else has to be
+// a valid CloneGoal and gets the same checks and size limit as other JSON bodies.
+#[post("/goals/{id}/clone")]
+async fn clone_goal(
+    req: HttpRequest,
+    user: User,
+    path: web::Path<Uuid>,
+    query: web::Query<AllowPastQuery>,
+    body: Result<web::Json<CloneGoal>, actix_web::Error>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let options = match body {
+        Ok(body) => body.into_inner(),
+        Err(_) if !req.headers().contains_key(header::CONTENT_TYPE) => CloneGoal { due_date: None },
+        // The response json_config built for the error
+        Err(e) => return e.error_response(),
+    };
+    let due_date = match options.due_date {
+        Some(due_date) => match checked_due_date(&due_date, query.allow_past.unwrap_or(false)) {
+            Ok(date) => Some(date.to_string()),
+            Err(e) => return ApiError::bad_request(e),
+        },
+        None => None,
+    };
+
+    let _writing = user.writes.lock().await;
+    let new_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(source) = goals.iter().find(|g| g.id == id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        let sub_goals = source
+            .sub_goals
+            .iter()
+            .map(|s| SubGoal { id: Uuid::new_v4(), title: s.title.clone(), completed: false, progress: 0 })
+            .collect();
+        let new_goal = Goal {
+            id: Uuid::new_v4(),
+            due_date: due_date.unwrap_or_else(|| source.due_date.clone()),
+            progress: 0,
+            sub_goals,
+            history: Vec::new(),
+            ..source.clone()
+        };
+        goals.push(new_goal.clone());
+        new_goal
+    };
+    if persistence::save_goal(&user, &new_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "create", new_goal.id);
+    HttpResponse::Ok().json(new_goal)
+}
+
+// A new `due_date` is checked like in create_goal, including ?allow_past=true
+#[patch("/goals/{id}")]
+async fn patch_goal(
+    user: User,
+    path: web::Path<Uuid>,
+    query: web::Query<AllowPastQuery>,
+    patch: web::Json<PatchGoal>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let mut patch = patch.into_inner();
+    if let Some(title) = patch.title {
+        match trimmed_title(&title, "Goal") {
+            Ok(title) => patch.title = Some(title),
+            Err(e) => return ApiError::bad_request(e),
+        }
+    }
+    if let Some(due_date) = patch.due_date {
+        match checked_due_date(&due_date, query.allow_past.unwrap_or(false)) {
+            Ok(date) => patch.due_date = Some(date.to_string()),
+            Err(e) => return ApiError::bad_request(e),
+        }
+    }
+    let _writing = user.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        if let Some(title) = patch.title {
+            goal.title = title;
+        }
+        if let Some(description) = patch.description {
+            goal.description = description;
+        }
+        if let Some(priority) = patch.priority {
+            goal.priority = priority;
+        }
+        if let Some(due_date) = patch.due_date {
+            goal.due_date = due_date;
+        }
+        goal.clone()
+    };
+    if persistence::save_goal(&user, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "update", id);
+    user.goal_updates.broadcast(&updated_goal);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[put("/goals/{id}/progress")]
+async fn update_progress(
+    user: User,
+    path: web::Path<Uuid>,
+    progress: web::Json<UpdateProgress>,
+) -> impl Responder {
+    let id = path.into_inner(); // Destructure `web::Path` here
+    if progress.progress > MAX_PROGRESS {
+        return ApiError::bad_request(format!("progress must be between 0 and {}", MAX_PROGRESS));
+    }
+    let _writing = user.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        goal.progress = progress.progress;
+        goal.history.push(ProgressEntry { timestamp: Local::now().to_rfc3339(), progress: progress.progress });
+        goal.clone()
+    };
+    if persistence::save_goal(&user, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "update", id);
+    user.goal_updates.broadcast(&updated_goal);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[get("/goals/{id}/history")]
+async fn get_goal_history(path: web::Path<Uuid>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    let goals = lock(&user.goals);
+    match goals.iter().find(|g| g.id == id) {
+        Some(goal) => HttpResponse::Ok().json(&goal.history),
+        None => ApiError::not_found("Goal not found"),
+    }
+}
+
+#[post("/goals/{id}/complete")]
+async fn complete_goal(user: User, path: web::Path<Uuid>) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = user.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        goal.progress = MAX_PROGRESS;
+        for sub_goal in goal.sub_goals.iter_mut() {
+            sub_goal.completed = true;
+        }
+        goal.clone()
+    };
+    if persistence::save_goal(&user, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "complete", id);
+    user.goal_updates.broadcast(&updated_goal);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[post("/goals/{id}/subgoals")]
+async fn add_sub_goal(
+    user: User,
+    path: web::Path<Uuid>,
+    sub_goal: web::Json<CreateSubGoal>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = user.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        goal.sub_goals.push(SubGoal {
+            id: Uuid::new_v4(),
+            title: sub_goal.title.clone(),
+            completed: false,
+            progress: 0,
+        });
+        recompute_progress(goal);
+        goal.clone()
+    };
+    if persistence::save_goal(&user, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "update", id);
+    user.goal_updates.broadcast(&updated_goal);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[put("/goals/{goal_id}/subgoals/{sub_id}/toggle")]
+async fn toggle_sub_goal(
+    user: User,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Responder {
+    let (goal_id, sub_id) = path.into_inner();
+    let _writing = user.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == goal_id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        let Some(sub_goal) = goal.sub_goals.iter_mut().find(|s| s.id == sub_id) else {
+            return ApiError::not_found("Sub-goal not found");
+        };
+        sub_goal.completed = !sub_goal.completed;
+        recompute_progress(goal);
+        goal.clone()
+    };
+    if persistence::save_goal(&user, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "update", goal_id);
+    user.goal_updates.broadcast(&updated_goal);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[put("/goals/{from_id}/subgoals/{sub_id}/move/{to_id}")]
+async fn move_sub_goal(
+    user: User,
+    path: web::Path<(Uuid, Uuid, Uuid)>,
+) -> impl Responder {
+    let (from_id, sub_id, to_id) = path.into_inner();
+    let _writing = user.writes.lock().await;
+    let moved = {
+        let mut goals = lock(&user.goals);
+        let (Some(from), Some(to)) = (
+            goals.iter().position(|g| g.id == from_id),
+            goals.iter().position(|g| g.id == to_id),
+        ) else {
+            return ApiError::not_found("Goal not found");
+        };
+        let Some(sub_index) = goals[from].sub_goals.iter().position(|s| s.id == sub_id) else {
+            return ApiError::not_found("Sub-goal not found");
+        };
+
+        let sub_goal = goals[from].sub_goals.remove(sub_index);
+        goals[to].sub_goals.push(sub_goal);
+        recompute_progress(&mut goals[from]);
+        recompute_progress(&mut goals[to]);
+        MovedSubGoal { from: goals[from].clone(), to: goals[to].clone() }
+    };
+    if persistence::save_goals(&user, &[moved.from.clone(), moved.to.clone()]).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "update", from_id);
+    user.audit.record("goal", "update", to_id);
+    user.goal_updates.broadcast(&moved.from);
+    user.goal_updates.broadcast(&moved.to);
+    HttpResponse::Ok().json(moved)
+}
+
+#[delete("/goals/{goal_id}/subgoals/{sub_id}")]
+async fn delete_sub_goal(
+    user: User,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Responder {
+    let (goal_id, sub_id) = path.into_inner();
+    let _writing = user.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&user.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == goal_id) else {
+            return ApiError::not_found("Goal not found");
+        };
+        if !goal.sub_goals.iter().any(|s| s.id == sub_id) {
+            return ApiError::not_found("Sub-goal not found");
+        }
+
+        goal.sub_goals.retain(|s| s.id != sub_id);
+        recompute_progress(goal);
+        goal.clone()
+    };
+    if persistence::save_goal(&user, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "update", goal_id);
+    user.goal_updates.broadcast(&updated_goal);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[delete("/goals/{id}")]
+async fn delete_goal(path: web::Path<Uuid>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = user.writes.lock().await;
+    {
+        let mut goals = lock(&user.goals);
+        if !goals.iter().any(|g| g.id == id) {
+            return ApiError::not_found("Goal not found");
+        }
+        goals.retain(|g| g.id != id);
+    }
+    if persistence::delete_goal(&user, id).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("goal", "delete", id);
+    HttpResponse::Ok().finish()
+}
+
+// Dashboard counts: tasks dated today, incomplete tasks dated before today,
+// unfinished goals due within the next 7 days and the mean progress of all goals
+#[get("/summary")]
+async fn get_summary(user: User) -> impl Responder {
+    let today = Local::now().date_naive();
+    let week_end = today + chrono::Duration::days(7);
+
+    let tasks = lock(&user.tasks);
+    let tasks_due_today = tasks.iter().filter(|t| !t.deleted && parse_date(&t.date) == Some(today)).count();
+    let overdue_tasks = tasks
+        .iter()
+        .filter(|t| !t.completed && !t.deleted && parse_date(&t.date).is_some_and(|d| d < today))
+        .count();
+    drop(tasks);
+
+    let goals = lock(&user.goals);
+    let goals_due_this_week = goals
+        .iter()
+        .filter(|g| g.progress < MAX_PROGRESS)
+        .filter(|g| parse_date(&g.due_date).is_some_and(|d| d >= today && d <= week_end))
+        .count();
+    let avg_goal_progress = if goals.is_empty() {
+        0.0
+    } else {
+        goals.iter().map(|g| g.progress as f64).sum::<f64>() / goals.len() as f64
+    };
+
+    HttpResponse::Ok().json(DailySummary {
+        tasks_due_today,
+        overdue_tasks,
+        goals_due_this_week,
+        avg_goal_progress,
+    })
+}
+
+#[get("/export")]
+async fn export_data(user: User) -> impl Responder {
+    let export = ExportData {
+        tasks: lock(&user.tasks).clone(),
+        comments: lock(&user.comments).clone(),
+        goals: lock(&user.goals).clone(),
+    };
+    HttpResponse::Ok().json(export)
+}
+
+// Replaces the current data with an /export payload, or appends to it with ?merge=true.
+// Merged tasks and comments get fresh ids, and merged goals whose id is taken get a new one.
+// Archived tasks are kept either way. Nothing is imported if any record is invalid, see checked_import.
+#[post("/import")]
+async fn import_data(
+    query: web::Query<ImportQuery>,
+    import: web::Json<ExportData>,
+    user: User,
+) -> impl Responder {
+    let merge = query.merge.unwrap_or(false);
+    let _writing = user.writes.lock().await;
+    let archived_ids: HashSet<u32> = lock(&user.archived_tasks).iter().filter_map(|t| t.id).collect();
+    let import = match checked_import(import.into_inner(), merge, &archived_ids) {
+        Ok(import) => import,
+        Err(e) => return ApiError::bad_request(e),
+    };
+    let summary = ImportSummary {
+        tasks: import.tasks.len(),
+        comments: import.comments.len(),
+        goals: import.goals.len(),
+    };
+
+    let (removed_tasks, added_tasks) = {
+        let mut tasks = lock(&user.tasks);
+        let removed_tasks: Vec<u32> = if merge { vec![] } else { tasks.drain(..).filter_map(|t| t.id).collect() };
+        if !merge {
+            // The tasks these keys created are gone, and their ids may be handed out again
+            lock(&user.idempotency_keys).clear();
+        }
+        let mut added_tasks = Vec::new();
+        for mut task in import.tasks {
+            if merge || task.id.is_none() {
+                task.id = Some(user.next_task_id.fetch_add(1, Ordering::SeqCst));
+            }
+            added_tasks.push(task.clone());
+            tasks.push(task);
+        }
+        // Keep the counter ahead of any imported id so later tasks can't collide
+        let max_task_id = tasks.iter().filter_map(|t| t.id).max().unwrap_or(0);
+        user.next_task_id.fetch_max(max_task_id + 1, Ordering::SeqCst);
+        (removed_tasks, added_tasks)
+    };
+
+    let added_comments = {
+        let mut comments = lock(&user.comments);
+        if !merge {
+            comments.clear();
+        }
+        let mut added_comments = Vec::new();
+        for mut comment in import.comments {
+            if merge || comment.id.is_none() {
+                comment.id = Some(user.next_comment_id.fetch_add(1, Ordering::SeqCst));
+            }
+            added_comments.push(comment.clone());
+            comments.push(comment);
+        }
+        let max_comment_id = comments.iter().filter_map(|c| c.id).max().unwrap_or(0);
+        user.next_comment_id.fetch_max(max_comment_id + 1, Ordering::SeqCst);
+        added_comments
+    };
+
+    let added_goals = {
+        let mut goals = lock(&user.goals);
+        if !merge {
+            goals.clear();
+        }
+        let mut added_goals = Vec::new();
+        for mut goal in import.goals {
+            if goals.iter().any(|g| g.id == goal.id) {
+                goal.id = Uuid::new_v4();
+            }
+            added_goals.push(goal.clone());
+            goals.push(goal);
+        }
+        added_goals
+    };
+
+    let saved = persistence::import(&user, !merge, &added_tasks, &added_comments, &added_goals).await;
+    if saved.is_err() {
+        return ApiError::storage_failed();
+    }
+    user.audit.record("data", "import", if merge { "merge" } else { "replace" });
+    for id in removed_tasks {
+        user.task_updates.broadcast(&TaskEvent::Deleted { id });
+    }
+    for task in added_tasks {
+        user.task_updates.broadcast(&TaskEvent::Added { task });
+    }
+    HttpResponse::Ok().json(summary)
+}
+
+// Back to a fresh install: no tasks or goals, the seed comments (if SEED_DATA is on) and empty bot state.
+// Only available with DEV_MODE, otherwise answers 404 as if the route didn't exist.
+#[post("/admin/reset")]
+async fn reset_data(data: web::Data<AppState>, bot_data: web::Data<BotAppState>) -> impl Responder {
+    if !data.dev_mode {
+        return ApiError::not_found("Not found");
+    }
+
+    lock(&data.users).entry(user::DEFAULT_USER.to_string()).or_default();
+    let users: Vec<(String, Arc<UserData>)> =
+        lock(&data.users).iter().map(|(id, user)| (id.clone(), Arc::clone(user))).collect();
+    // No user's writes, nor the bot's, can run in between emptying the database and the caches
+    let mut writing = Vec::new();
+    for (_, user) in &users {
+        writing.push(user.writes.lock().await);
+    }
+    let _bot_writing = bot_data.writes.lock().await;
+    if persistence::reset(&data.db, &seed_data(data.seed_data)).await.is_err() {
+        return ApiError::storage_failed();
+    }
+
+    for (id, user) in &users {
+        let stored = seed_data(data.seed_data && id == user::DEFAULT_USER);
+        let removed_tasks: Vec<u32> = std::mem::replace(&mut *lock(&user.tasks), stored.tasks)
+            .into_iter()
+            .filter_map(|t| t.id)
+            .collect();
+        lock(&user.idempotency_keys).clear();
+        *lock(&user.archived_tasks) = stored.archived_tasks;
+        user.next_task_id.store(stored.next_task_id, Ordering::SeqCst);
+        user.next_comment_id.store(stored.next_comment_id, Ordering::SeqCst);
+        *lock(&user.comments) = stored.comments;
+        *lock(&user.goals) = stored.goals;
+        for id in removed_tasks {
+            user.task_updates.broadcast(&TaskEvent::Deleted { id });
+        }
+    }
+
+    lock(&bot_data.tasks).clear();
+    lock(&bot_data.goals).clear();
+    lock(&bot_data.pomodoro_sessions).clear();
+
+    data.audit.record("data", "reset", "all");
+    HttpResponse::Ok().finish()
+}
+
+#[get("/api/music")]
+async fn get_music_catalog(data: web::Data<AppState>) -> impl Responder {
+    let catalog: Vec<MusicCategory> = MUSIC_CATEGORIES
+        .iter()
+        .map(|(category, file)| MusicCategory {
+            category: category.to_string(),
+            url: music_url(&data.music_base_url, file),
+        })
+        .collect();
+    HttpResponse::Ok().json(catalog)
+}
+
+#[get("/api/music/{category}")]
+async fn get_music(category: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    // Map categories to music URLs
+    match MUSIC_CATEGORIES.iter().find(|(name, _)| *name == category.as_str()) {
+        Some((_, file)) => HttpResponse::Ok().json(MusicResponse { url: music_url(&data.music_base_url, file) }),
+        None => {
+            let valid: Vec<&str> = MUSIC_CATEGORIES.iter().map(|(name, _)| *name).collect();
+            ApiError::not_found(format!(
+                "Unknown music category: {}. Valid categories: {}",
+                category,
+                valid.join(", ")
+            ))
+        }
+    }
+}
+
+// Bot routes
+// Accepts optional `pomodoro` and `completed` query params, e.g. /bot/tasks?pomodoro=true&completed=false
+#[get("/bot/tasks")]
+async fn get_bot_tasks(query: web::Query<BotTaskFilter>, data: web::Data<BotAppState>) -> impl Responder {
+    let tasks = lock(&data.tasks);
+    let filtered: Vec<BotTask> = tasks
+        .iter()
+        .filter(|t| query.pomodoro.is_none_or(|p| t.is_pomodoro == p))
+        .filter(|t| query.completed.is_none_or(|c| t.completed == c))
+        .cloned()
+        .collect();
+    HttpResponse::Ok().json(filtered)
+}
+
+#[post("/bot/tasks")]
+async fn add_bot_task(task: web::Json<BotTask>, data: web::Data<BotAppState>) -> impl Responder {
+    let _writing = data.writes.lock().await;
+    let mut new_task = task.into_inner();
+    let task_id = Uuid::new_v4();
+    new_task.id = Some(task_id);
+    lock(&data.tasks).push(new_task.clone());
+    if persistence::save_bot_task(&data, &new_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_task", "create", task_id);
+    HttpResponse::Ok().json(new_task)
+}
+
+// Update bot task
+#[put("/bot/tasks/{id}")]
+async fn update_bot_task(
+    path: web::Path<Uuid>,
+    task: web::Json<BotTask>,
+    data: web::Data<BotAppState>
+) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = data.writes.lock().await;
+    let updated_task = {
+        let mut tasks = lock(&data.tasks);
+
+        // Find the task with the provided ID and update it
+        let Some(existing_task) = tasks.iter_mut().find(|t| t.id == Some(id)) else {
+            return ApiError::not_found("Bot task not found");
+        };
+        existing_task.title = task.title.clone();
+        existing_task.completed = task.completed;
+        existing_task.is_pomodoro = task.is_pomodoro;
+        existing_task.clone()
+    };
+    if persistence::save_bot_task(&data, &updated_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_task", "update", id);
+    HttpResponse::Ok().json(updated_task)
+}
+
+#[post("/bot/tasks/complete/{id}")]
+async fn complete_bot_task(task_id: web::Path<Uuid>, data: web::Data<BotAppState>) -> impl Responder {
+    let _writing = data.writes.lock().await;
+    let task_id = task_id.into_inner();
+    let completed_task = {
+        let mut tasks = lock(&data.tasks);
+        let Some(task) = tasks.iter_mut().find(|task| task.id == Some(task_id)) else {
+            return ApiError::not_found("Bot task not found");
+        };
+        task.completed = true;
+        task.clone()
+    };
+    if persistence::save_bot_task(&data, &completed_task).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_task", "complete", task_id);
+    HttpResponse::Ok().json(completed_task)
+}
+
+
+#[get("/bot/goals")]
+async fn get_bot_goals(data: web::Data<BotAppState>) -> impl Responder {
+    let goals = lock(&data.goals);
+    HttpResponse::Ok().json(goals.clone())
+}
+
+#[post("/bot/goals")]
+async fn add_bot_goal(
+    goal: web::Json<BotGoal>,
+    data: web::Data<BotAppState>
+) -> impl Responder {
+    let _writing = data.writes.lock().await;
+    let mut new_goal = goal.into_inner();
+    let goal_id = Uuid::new_v4(); // Assign a new UUID
+    new_goal.id = Some(goal_id);
+    lock(&data.goals).push(new_goal.clone());
+    if persistence::save_bot_goal(&data, &new_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_goal", "create", goal_id);
+    HttpResponse::Ok().json(new_goal)
+}
+
+#[put("/bot/goals/{id}")]
+async fn update_bot_goal(
+    path: web::Path<Uuid>,
+    goal: web::Json<BotGoal>,
+    data: web::Data<BotAppState>
+) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = data.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&data.goals);
+        let Some(existing_goal) = goals.iter_mut().find(|g| g.id == Some(id)) else {
+            return ApiError::not_found("Bot goal not found");
+        };
+        existing_goal.title = goal.title.clone();
+        existing_goal.progress = goal.progress.min(MAX_PROGRESS as u32);
+        existing_goal.clone()
+    };
+    if persistence::save_bot_goal(&data, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_goal", "update", id);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[put("/bot/goals/{id}/progress")]
+async fn update_bot_goal_progress(
+    path: web::Path<Uuid>,
+    progress: web::Json<UpdateBotProgress>,
+    data: web::Data<BotAppState>
+) -> impl Responder {
+    let id = path.into_inner();
+    let _writing = data.writes.lock().await;
+    let updated_goal = {
+        let mut goals = lock(&data.goals);
+        let Some(goal) = goals.iter_mut().find(|g| g.id == Some(id)) else {
+            return ApiError::not_found("Bot goal not found");
+        };
+        goal.progress = progress.progress.min(MAX_PROGRESS as u32);
+        goal.clone()
+    };
+    if persistence::save_bot_goal(&data, &updated_goal).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_goal", "update", id);
+    HttpResponse::Ok().json(updated_goal)
+}
+
+#[post("/bot/tasks/{id}/pomodoro/start")]
+async fn start_pomodoro(task_id: web::Path<Uuid>, data: web::Data<BotAppState>) -> impl Responder {
+    let task_id = task_id.into_inner();
+    if !lock(&data.tasks).iter().any(|task| task.id == Some(task_id)) {
+        return ApiError::not_found("Bot task not found");
+    }
+
+    let mut sessions = lock(&data.pomodoro_sessions);
+    if sessions.iter().any(|s| s.task_id == task_id && s.ended_at.is_none()) {
+        return ApiError::conflict("A pomodoro session is already running for this task");
+    }
+    sessions.push(PomodoroSession { task_id, started_at: Local::now(), ended_at: None });
+    HttpResponse::Ok().json(pomodoro_summary(task_id, &sessions))
+}
+
+#[post("/bot/tasks/{id}/pomodoro/stop")]
+async fn stop_pomodoro(task_id: web::Path<Uuid>, data: web::Data<BotAppState>) -> impl Responder {
+    let task_id = task_id.into_inner();
+    if !lock(&data.tasks).iter().any(|task| task.id == Some(task_id)) {
+        return ApiError::not_found("Bot task not found");
+    }
+
+    let mut sessions = lock(&data.pomodoro_sessions);
+    if let Some(session) = sessions.iter_mut().find(|s| s.task_id == task_id && s.ended_at.is_none()) {
+        session.ended_at = Some(Local::now());
+        HttpResponse::Ok().json(pomodoro_summary(task_id, &sessions))
+    } else {
+        ApiError::conflict("No pomodoro session is running for this task")
+    }
+}
+
+#[get("/bot/tasks/{id}/pomodoro")]
+async fn get_pomodoro(task_id: web::Path<Uuid>, data: web::Data<BotAppState>) -> impl Responder {
+    let task_id = task_id.into_inner();
+    if !lock(&data.tasks).iter().any(|task| task.id == Some(task_id)) {
+        return ApiError::not_found("Bot task not found");
+    }
+
+    let sessions = lock(&data.pomodoro_sessions);
+    HttpResponse::Ok().json(pomodoro_summary(task_id, &sessions))
+}
+
+#[delete("/bot/tasks/{id}")]
+async fn delete_bot_task(task_id: web::Path<Uuid>, data: web::Data<BotAppState>) -> impl Responder {
+    let _writing = data.writes.lock().await;
+    let task_id = task_id.into_inner();
+    {
+        let mut tasks = lock(&data.tasks);
+        if tasks.iter().position(|task| task.id == Some(task_id)).is_none() {
+            return ApiError::not_found("Bot task not found");
+        }
+        tasks.retain(|task| task.id != Some(task_id));
+    }
+    if persistence::delete_bot_tasks(&data, &[task_id]).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_task", "delete", task_id);
+    HttpResponse::Ok().finish()
+}
+
+// Bot task ids are UUIDs, so the body is a list of them rather than numbers
+#[post("/bot/tasks/delete-batch")]
+async fn delete_bot_tasks_batch(ids: web::Json<Vec<Uuid>>, data: web::Data<BotAppState>) -> impl Responder {
+    let _writing = data.writes.lock().await;
+    let mut result = BatchDeleteResult { deleted: vec![], not_found: vec![] };
+    {
+        let mut tasks = lock(&data.tasks);
+        for id in ids.into_inner() {
+            if tasks.iter().any(|task| task.id == Some(id)) {
+                tasks.retain(|task| task.id != Some(id));
+                result.deleted.push(id);
+            } else {
+                result.not_found.push(id);
+            }
+        }
+    }
+    if !result.deleted.is_empty() && persistence::delete_bot_tasks(&data, &result.deleted).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    for id in &result.deleted {
+        data.audit.record("bot_task", "delete", id);
+    }
+    HttpResponse::Ok().json(result)
+}
+
+#[delete("/bot/goals/{id}")]
+async fn delete_bot_goal(goal_id: web::Path<Uuid>, data: web::Data<BotAppState>) -> impl Responder {
+    let _writing = data.writes.lock().await;
+    let goal_id = goal_id.into_inner();
+    {
+        let mut goals = lock(&data.goals);
+        if !goals.iter().any(|goal| goal.id == Some(goal_id)) {
+            return ApiError::not_found("Bot goal not found");
+        }
+        goals.retain(|goal| goal.id != Some(goal_id));
+    }
+    if persistence::delete_bot_goal(&data, goal_id).await.is_err() {
+        return ApiError::storage_failed();
+    }
+    data.audit.record("bot_goal", "delete", goal_id);
+    HttpResponse::Ok().finish()
+}
+
+// Bodies over `max_body_bytes` (MAX_BODY_BYTES) get 413, anything else that isn't valid JSON for the handler 400
+fn json_config(max_body_bytes: usize) -> web::JsonConfig {
+    web::JsonConfig::default().limit(max_body_bytes).error_handler(|err, _req| {
+        let response = match err {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                let body = ApiError { error: "payload_too_large", detail: err.to_string() };
+                HttpResponse::PayloadTooLarge().json(body)
+            }
+            _ => {
+                let body = ApiError { error: "invalid_json", detail: err.to_string() };
+                HttpResponse::BadRequest().json(body)
+            }
+        };
+        InternalError::from_response(err, response).into()
+    })
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // RUST_LOG controls the level, e.g. RUST_LOG=warn to quiet request logs
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let config = config::Config::from_env()?;
+
+    let (db, created) = persistence::connect(&config.database_url).await?;
+    if created {
+        // Carry over the data.json of earlier versions, or start fresh
+        let initial = persistence::load_legacy_file()?.unwrap_or_else(|| seed_data(config.seed_data));
+        persistence::initialize(&db, &initial).await?;
+    }
+    let users = persistence::load(&db).await?
+        .into_iter()
+        .map(|(id, stored)| (id, Arc::new(UserData::new(stored))))
+        .collect();
+    let (bot_tasks, bot_goals) = persistence::load_bot(&db).await?;
+
+    let audit = Arc::new(audit::AuditLog::new(config.audit_log_path.clone()));
+
+    let app_state = web::Data::new(AppState {
+        users: Mutex::new(users),
+        started_at: Instant::now(),
+        db: db.clone(),
+        audit: Arc::clone(&audit),
+        music_base_url: config.music_base_url.clone(),
+        dev_mode: config.dev_mode,
+        seed_data: config.seed_data,
+    });
+
+    let bot_state = web::Data::new(BotAppState {
+        tasks: Mutex::new(bot_tasks),
+        goals: Mutex::new(bot_goals),
+        pomodoro_sessions: Mutex::new(vec![]),
+        db,
+        writes: tokio::sync::Mutex::new(()),
+        audit,
+    });
+
+    let api_key = web::Data::new(auth::ApiKey(config.api_key.clone()));
+    let rate_limiter = web::Data::new(rate_limit::RateLimiter::new(config.rate_limit_per_minute));
+    let request_metrics = web::Data::new(metrics::RequestMetrics::new());
+    let allowed_origins = config.allowed_origins.clone();
+    let max_body_bytes = config.max_body_bytes;
+    let shutdown_db = app_state.db.clone();
+
+    let mut server = HttpServer::new(move || {
+        let cors = match &allowed_origins {
+            Some(origins) => origins.iter().fold(Cors::default(), |cors, origin| cors.allowed_origin(origin)),
+            None => Cors::default().allow_any_origin(),
+        };
+
+        App::new()
+            .app_data(json_config(max_body_bytes))
+            .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+                let body = ApiError { error: "invalid_query", detail: err.to_string() };
+                InternalError::from_response(err, HttpResponse::BadRequest().json(body)).into()
+            }))
+            .app_data(app_state.clone())
+            .app_data(bot_state.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(api_key.clone())
+            .app_data(request_metrics.clone())
+            .wrap(middleware::from_fn(auth::require_api_key))
+            .wrap(middleware::from_fn(rate_limit::rate_limit))
+            .wrap(middleware::from_fn(metrics::track_requests))
+            .wrap(middleware::from_fn(request_id::assign_request_id))
+            // Request id, client, request line, status, response size and time taken
+            .wrap(middleware::Logger::new(r#"%{x-request-id}o %a "%r" %s %b %Dms"#))
+            .wrap(cors
+                .allow_any_method()
+                .allow_any_header()
+                .expose_headers([request_id::HEADER])
+            )
+            .service(current_date)
+            .service(current_date_stream)
+            .service(health)
+            .service(get_metrics)
+            .service(get_tasks)
+            .service(get_tasks_today)
+            .service(get_tasks_by_date)
+            .service(get_task_stats)
+            .service(get_task_tags)
+            .service(get_task_conflicts)
+            .service(get_task_streak)
+            .service(get_upcoming_tasks)
+            .service(get_completed_tasks)
+            .service(get_archived_tasks)
+            .service(export_tasks_csv)
+            .service(get_trashed_tasks)
+            // After the literal /tasks/... GET routes so they aren't taken as ids
+            .service(get_task)
+            .service(add_task)
+            .service(archive_tasks)
+            .service(complete_task)
+            .service(complete_tasks_batch)
+            .service(incomplete_task)
+            .service(toggle_task)
+            .service(complete_recurring_task)
+            .service(snooze_task)
+            .service(duplicate_task)
+            .service(update_task)
+            .service(add_task_tag)
+            .service(remove_task_tag)
+            .service(clear_completed_tasks)
+            .service(empty_trash)
+            .service(delete_task)
+            .service(restore_task)
+            .service(task_updates_ws)
+            .service(goal_updates_ws)
+            .service(get_comments)
+            .service(get_comment_count)
+            .service(get_comment)
+            .service(add_comment)
+            .service(update_comment)
+            .service(delete_comment)
+            .service(search)
+            .service(get_goals)
+            .service(get_overdue_goals)
+            .service(get_remaining_work)
+            .service(get_goal)
+            .service(create_goal)
+            .service(clone_goal)
+            .service(patch_goal)
+            .service(update_progress)
+            .service(get_goal_history)
+            .service(delete_goal)
+            .service(complete_goal)
+            .service(add_sub_goal)
+            .service(toggle_sub_goal)
+            .service(move_sub_goal)
+            .service(delete_sub_goal)
+            .service(get_bot_tasks)
+            .service(add_bot_task)
+            .service(update_bot_task)
+            .service(complete_bot_task)
+            .service(delete_bot_task) // Added delete route
+            .service(delete_bot_tasks_batch)
+            .service(start_pomodoro)
+            .service(stop_pomodoro)
+            .service(get_pomodoro)
+            .service(get_bot_goals)
+            .service(add_bot_goal)
+            .service(update_bot_goal)
+            .service(update_bot_goal_progress)
+            .service(delete_bot_goal)
+            .service(get_summary)
+            .service(export_data)
+            .service(import_data)
+            .service(reset_data)
+            .service(get_music_catalog)
+            .service(get_music) // Add the music endpoint here
+
+    });
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    let server = server.bind((config.host.as_str(), config.port))?.run();
+
+    // actix stops accepting connections and drains in-flight requests on SIGINT/SIGTERM.
+    // Every request has already written its changes by then, so all that's left is to
+    // close the database cleanly; pomodoro sessions are in memory only and aren't kept.
+    server.await?;
+    shutdown_db.close().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use std::collections::HashSet;
+
+    // Fresh state backed by an in-memory database, so tests don't touch data.db or each other
+    async fn app_state() -> web::Data<AppState> {
+        let (db, _) = persistence::connect("sqlite::memory:").await.unwrap();
+        let audit_log_path = std::env::temp_dir().join("taskbar-backend-test-audit.log");
+        web::Data::new(AppState {
+            users: Mutex::new(HashMap::new()),
+            started_at: Instant::now(),
+            db,
+            audit: Arc::new(audit::AuditLog::new(audit_log_path.to_string_lossy().into_owned())),
+            music_base_url: String::new(),
+            dev_mode: false,
+            seed_data: false,
+        })
+    }
+
+    async fn bot_state() -> web::Data<BotAppState> {
+        let state = app_state().await;
+        web::Data::new(BotAppState {
+            tasks: Mutex::new(vec![]),
+            goals: Mutex::new(vec![]),
+            pomodoro_sessions: Mutex::new(vec![]),
+            db: state.db.clone(),
+            writes: tokio::sync::Mutex::new(()),
+            audit: Arc::clone(&state.audit),
+        })
+    }
+
+    fn new_task(title: &str) -> serde_json::Value {
+        serde_json::json!({ "title": title, "date": "2030-01-01", "completed": false, "priority": "low" })
+    }
+
+    #[actix_web::test]
+    async fn task_ids_are_not_reused_after_delete() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(add_task).service(delete_task)).await;
+
+        let mut ids = Vec::new();
+        for title in ["first", "second"] {
+            let req = test::TestRequest::post().uri("/tasks").set_json(new_task(title)).to_request();
+            let task: Task = test::call_and_read_body_json(&app, req).await;
+            ids.push(task.id.unwrap());
+        }
+        let req = test::TestRequest::delete().uri(&format!("/tasks/{}", ids[1])).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::post().uri("/tasks").set_json(new_task("third")).to_request();
+        let task: Task = test::call_and_read_body_json(&app, req).await;
+        assert!(!ids.contains(&task.id.unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn poisoned_lock_still_serves_requests() {
+        let state = app_state().await;
+        let user = Arc::new(UserData::default());
+        lock(&state.users).insert(user::DEFAULT_USER.to_string(), user.clone());
+        let result = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _tasks = lock(&user.tasks);
+                    panic!("poisoning the tasks lock");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+        assert!(user.tasks.is_poisoned());
+
+        let app = test::init_service(App::new().app_data(state).service(get_tasks)).await;
+        let req = test::TestRequest::get().uri("/tasks").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn users_only_see_their_own_tasks() {
+        let state = app_state().await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(add_task).service(get_tasks)).await;
+
+        let req = test::TestRequest::get().uri("/tasks").insert_header((user::HEADER, "bob")).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert!(!lock(&state.users).contains_key("bob"));
+
+        let req = test::TestRequest::post()
+            .uri("/tasks")
+            .insert_header((user::HEADER, "alice"))
+            .set_json(new_task("hers"))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get().uri("/tasks").insert_header((user::HEADER, "bob")).to_request();
+        let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 0);
+        let req = test::TestRequest::get().uri("/tasks").insert_header((user::HEADER, "alice")).to_request();
+        let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page["items"][0]["title"], "hers");
+    }
+
+    #[actix_web::test]
+    async fn progress_above_100_is_rejected() {
+        let app = test::init_service(
+            App::new().app_data(app_state().await).service(create_goal).service(get_goals).service(update_progress),
+        )
+        .await;
+        let body = serde_json::json!({ "title": "Run", "description": "", "priority": "low", "due_date": "2030-01-01" });
+        let req = test::TestRequest::post().uri("/goals").set_json(body).to_request();
+        let goal: Goal = test::call_and_read_body_json(&app, req).await;
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/goals/{}/progress", goal.id))
+            .set_json(serde_json::json!({ "progress": 150 }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        let req = test::TestRequest::get().uri("/goals").to_request();
+        let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page["items"][0]["progress"], 0);
+    }
+
+    #[actix_web::test]
+    async fn timestamps_keep_their_date() {
+        let app = test::init_service(
+            App::new().app_data(app_state().await).service(add_task).service(get_task_conflicts),
+        )
+        .await;
+        let mut overnight = new_task("Night shift");
+        overnight["start_time"] = "2030-01-01T22:00:00+00:00".into();
+        overnight["end_time"] = "2030-01-02T06:00:00+00:00".into();
+        let req = test::TestRequest::post().uri("/tasks").set_json(overnight).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let mut early = new_task("Breakfast");
+        early["date"] = "2030-01-02".into();
+        early["start_time"] = "05:00".into();
+        early["end_time"] = "2030-01-01T07:00:00+00:00".into();
+        let req = test::TestRequest::post().uri("/tasks").set_json(early.clone()).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        early["end_time"] = "07:00".into();
+        let req = test::TestRequest::post().uri("/tasks").set_json(early).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let req = test::TestRequest::get().uri("/tasks/conflicts").to_request();
+        let conflicts: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(conflicts.as_array().map(Vec::len), Some(1));
+        assert_eq!(conflicts[0]["first"]["title"], "Night shift");
+        assert_eq!(conflicts[0]["second"]["title"], "Breakfast");
+    }
+
+    #[actix_web::test]
+    async fn dedup_ignores_case_and_accents() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(add_task)).await;
+        let req = test::TestRequest::post().uri("/tasks?dedup=true").set_json(new_task("Café")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let req = test::TestRequest::post().uri("/tasks?dedup=true").set_json(new_task("CAFE")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 409);
+        let existing: Task = test::read_body_json(resp).await;
+        assert_eq!(existing.title, "Café");
+    }
+
+    #[actix_web::test]
+    async fn api_key_query_token_only_works_for_websockets() {
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state().await)
+                .app_data(web::Data::new(auth::ApiKey(Some("secret".to_string()))))
+                .wrap(middleware::from_fn(auth::require_api_key))
+                .service(get_tasks)
+                .service(task_updates_ws),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/tasks?token=secret").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        let req = test::TestRequest::get().uri("/ws/tasks").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        // Past the middleware; the handler then refuses the request as it isn't a WebSocket upgrade
+        let req = test::TestRequest::get().uri("/ws/tasks?token=secret").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn blank_titles_are_rejected() {
+        let app = test::init_service(
+            App::new().app_data(app_state().await).service(add_task).service(create_goal).service(add_comment),
+        )
+        .await;
+        let bodies = [
+            ("/tasks", new_task("   ")),
+            ("/goals", serde_json::json!({ "title": "   ", "description": "", "priority": "low", "due_date": "2030-01-01" })),
+            ("/comments", serde_json::json!({ "title": "   ", "content": "text" })),
+        ];
+        for (uri, body) in bodies {
+            let req = test::TestRequest::post().uri(uri).set_json(body).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400, "POST {}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn bot_task_ids_dont_collide_after_delete() {
+        let app = test::init_service(
+            App::new()
+                .app_data(bot_state().await)
+                .service(get_bot_tasks)
+                .service(add_bot_task)
+                .service(delete_bot_task),
+        )
+        .await;
+        let add = |title: &str| {
+            let body = serde_json::json!({ "title": title, "completed": false, "is_pomodoro": false });
+            test::TestRequest::post().uri("/bot/tasks").set_json(body).to_request()
+        };
+
+        let first: BotTask = test::call_and_read_body_json(&app, add("first")).await;
+        let _second: BotTask = test::call_and_read_body_json(&app, add("second")).await;
+        let req = test::TestRequest::delete().uri(&format!("/bot/tasks/{}", first.id.unwrap())).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let _third: BotTask = test::call_and_read_body_json(&app, add("third")).await;
+
+        let req = test::TestRequest::get().uri("/bot/tasks").to_request();
+        let tasks: Vec<BotTask> = test::call_and_read_body_json(&app, req).await;
+        let ids: HashSet<Uuid> = tasks.iter().filter_map(|t| t.id).collect();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(ids.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn oversized_body_gets_413() {
+        let app = test::init_service(App::new().app_data(app_state().await).app_data(json_config(1024)).service(add_comment))
+            .await;
+        let body = serde_json::json!({ "title": "Long", "content": "x".repeat(2048) });
+        let req = test::TestRequest::post().uri("/comments").set_json(body).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 413);
+    }
+
+    #[actix_web::test]
+    async fn clone_goal_body_is_optional_but_checked() {
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state().await)
+                .app_data(json_config(1024))
+                .service(create_goal)
+                .service(clone_goal),
+        )
+        .await;
+        let goal = serde_json::json!({ "title": "Run", "description": "", "priority": "high", "due_date": "2999-01-01" });
+        let req = test::TestRequest::post().uri("/goals").set_json(goal).to_request();
+        let goal: Goal = test::call_and_read_body_json(&app, req).await;
+        let uri = format!("/goals/{}/clone", goal.id);
+
+        let req = test::TestRequest::post().uri(&uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{\"due_date\":")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+        let body = serde_json::json!({ "due_date": "x".repeat(2048) });
+        let req = test::TestRequest::post().uri(&uri).set_json(body).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 413);
+    }
+
+    #[actix_web::test]
+    async fn import_rejects_duplicate_task_ids() {
+        let app = test::init_service(
+            App::new().app_data(app_state().await).service(add_task).service(import_data).service(get_tasks),
+        )
+        .await;
+        let req = test::TestRequest::post().uri("/tasks").set_json(new_task("Keep me")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let task = |title: &str| serde_json::json!({ "id": 7, "title": title, "date": "2024-06-01", "completed": false });
+        let import = serde_json::json!({ "tasks": [task("One"), task("Two")], "comments": [], "goals": [] });
+        let req = test::TestRequest::post().uri("/import").set_json(import).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let error: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(error["detail"], "tasks[1]: id 7 is used by more than one task");
+
+        let req = test::TestRequest::get().uri("/tasks").to_request();
+        let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 1);
+        assert_eq!(page["items"][0]["title"], "Keep me");
+    }
+}
@@ -0,0 +1,49 @@
+use actix_web::{web, Error};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::lock;
+
+// Request counts by method and response status, exposed on /metrics
+pub struct RequestMetrics {
+    counts: Mutex<BTreeMap<(String, u16), u64>>,
+}
+
+impl RequestMetrics {
+    pub fn new() -> Self {
+        RequestMetrics {
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn record(&self, method: &str, status: u16) {
+        *lock(&self.counts).entry((method.to_string(), status)).or_insert(0) += 1;
+    }
+
+    // Appends the counts in Prometheus text format
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP http_requests_total Requests handled, by method and status.");
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        for ((method, status), count) in lock(&self.counts).iter() {
+            let _ = writeln!(out, "http_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+        }
+    }
+}
+
+// Counts every request once its response is ready, including ones rejected by other middleware
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req.app_data::<web::Data<RequestMetrics>>().cloned();
+    let method = req.method().to_string();
+    let res = next.call(req).await?;
+    if let Some(metrics) = metrics {
+        metrics.record(&method, res.status().as_u16());
+    }
+    Ok(res)
+}
@@ -0,0 +1,525 @@
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use sqlx::query::Query;
+use sqlx::sqlite::{Sqlite, SqliteArguments, SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Row;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use uuid::Uuid;
+
+use crate::user::{User, DEFAULT_USER};
+use crate::{lock, BotAppState, BotGoal, BotTask, Comment, Goal, Task};
+
+// Written by earlier versions; imported when the database is first created
+const LEGACY_DATA_FILE: &str = "data.json";
+
+type Statement<'q> = Query<'q, Sqlite, SqliteArguments<'q>>;
+
+// Everything in a user's data that survives a restart
+#[derive(Deserialize, Default)]
+pub struct StoredData {
+    pub tasks: Vec<Task>,
+    #[serde(default)]
+    pub next_task_id: u32,
+    #[serde(default)]
+    pub archived_tasks: Vec<Task>,
+    #[serde(default)]
+    pub next_comment_id: u32,
+    pub comments: Vec<Comment>,
+    pub goals: Vec<Goal>,
+}
+
+// Opens (creating if needed) the database at DATABASE_URL and brings its schema up to date.
+// Also returns whether the database was just created.
+pub async fn connect(url: &str) -> io::Result<(SqlitePool, bool)> {
+    let options = SqliteConnectOptions::from_str(url).map_err(io::Error::other)?.create_if_missing(true);
+    // Writes are serialized by the `writes` locks anyway, and a single connection that is
+    // never recycled keeps `sqlite::memory:` databases alive for the life of the pool
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .map_err(io::Error::other)?;
+    let created = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = '_sqlx_migrations'")
+        .fetch_optional(&pool)
+        .await
+        .map_err(io::Error::other)?
+        .is_none();
+    sqlx::migrate!().run(&pool).await.map_err(io::Error::other)?;
+    Ok((pool, created))
+}
+
+// The data.json written by earlier versions, if there is one
+pub fn load_legacy_file() -> io::Result<Option<StoredData>> {
+    match fs::read_to_string(LEGACY_DATA_FILE) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Fills a newly created database, e.g. with the legacy data or the sample comments,
+// which belong to the default user
+pub async fn initialize(pool: &SqlitePool, stored: &StoredData) -> io::Result<()> {
+    run(pool, seed_statements(stored)).await.map_err(io::Error::other)
+}
+
+// Empties every table for /admin/reset, then fills them like `initialize`
+pub async fn reset(pool: &SqlitePool, stored: &StoredData) -> sqlx::Result<()> {
+    let statements = seed_statements(stored).map(|seed| {
+        let mut statements: Vec<Statement> = [
+            "DELETE FROM tasks",
+            "DELETE FROM comments",
+            "DELETE FROM goals",
+            "DELETE FROM counters",
+            "DELETE FROM bot_tasks",
+            "DELETE FROM bot_goals",
+        ]
+        .into_iter()
+        .map(sqlx::query)
+        .collect();
+        statements.extend(seed);
+        statements
+    });
+    let result = run(pool, statements).await;
+    if let Err(e) = &result {
+        log::error!("Failed to reset data: {}", e);
+    }
+    result
+}
+
+fn seed_statements(stored: &StoredData) -> sqlx::Result<Vec<Statement<'_>>> {
+    let mut statements = vec![
+        set_counter(DEFAULT_USER, "next_task_id", stored.next_task_id),
+        set_counter(DEFAULT_USER, "next_comment_id", stored.next_comment_id),
+    ];
+    for task in &stored.tasks {
+        statements.push(upsert_task(DEFAULT_USER, task)?);
+    }
+    for comment in &stored.comments {
+        statements.push(upsert_comment(DEFAULT_USER, comment)?);
+    }
+    for goal in &stored.goals {
+        statements.push(upsert_goal(DEFAULT_USER, goal)?);
+    }
+    Ok(statements)
+}
+
+// The stored data of every user that has any, with their ids
+pub async fn load(pool: &SqlitePool) -> io::Result<Vec<(String, StoredData)>> {
+    let user_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT user_id FROM tasks UNION SELECT user_id FROM comments \
+         UNION SELECT user_id FROM goals UNION SELECT user_id FROM counters",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(io::Error::other)?;
+
+    let mut users = Vec::new();
+    for user_id in user_ids {
+        let stored = read_data(pool, &user_id).await.map_err(io::Error::other)?;
+        users.push((user_id, stored));
+    }
+    Ok(users)
+}
+
+async fn read_data(pool: &SqlitePool, user_id: &str) -> sqlx::Result<StoredData> {
+    let tasks = sqlx::query("SELECT * FROM tasks WHERE user_id = ? AND archived = 0 ORDER BY rowid")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows.iter().map(task_from_row).collect())?;
+    let next_task_id = read_counter(pool, user_id, "next_task_id").await?;
+    let archived_tasks = sqlx::query("SELECT * FROM tasks WHERE user_id = ? AND archived = 1 ORDER BY rowid")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows.iter().map(task_from_row).collect())?;
+    let next_comment_id = read_counter(pool, user_id, "next_comment_id").await?;
+    let comments = sqlx::query("SELECT * FROM comments WHERE user_id = ? ORDER BY rowid")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows.iter().map(comment_from_row).collect())?;
+    let goals = sqlx::query("SELECT * FROM goals WHERE user_id = ? ORDER BY rowid")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows.iter().map(goal_from_row).collect())?;
+    Ok(StoredData { tasks, next_task_id, archived_tasks, next_comment_id, comments, goals })
+}
+
+// 0 when the counter was never stored
+async fn read_counter(pool: &SqlitePool, user_id: &str, name: &str) -> sqlx::Result<u32> {
+    let value = sqlx::query_scalar("SELECT value FROM counters WHERE user_id = ? AND name = ?")
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+    Ok(value.unwrap_or(0))
+}
+
+pub async fn load_bot(pool: &SqlitePool) -> io::Result<(Vec<BotTask>, Vec<BotGoal>)> {
+    read_bot_data(pool).await.map_err(io::Error::other)
+}
+
+async fn read_bot_data(pool: &SqlitePool) -> sqlx::Result<(Vec<BotTask>, Vec<BotGoal>)> {
+    let tasks = sqlx::query("SELECT * FROM bot_tasks ORDER BY rowid")
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows.iter().map(bot_task_from_row).collect())?;
+    let goals = sqlx::query("SELECT * FROM bot_goals ORDER BY rowid")
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows.iter().map(bot_goal_from_row).collect())?;
+    Ok((tasks, goals))
+}
+
+// Each save below writes just the rows a handler changed, in one transaction. Handlers hold
+// the user's (or the bot state's) `writes` lock from changing the cached collections until
+// the save returns. When a save fails the collections are reloaded from the database, so
+// they don't keep a change that wasn't stored, and the handler answers 500.
+
+// Tasks that include one that was just given an id, along with the advanced counter
+pub async fn insert_tasks(user: &User, tasks: &[Task]) -> sqlx::Result<()> {
+    let statements = tasks.iter().map(|task| upsert_task(&user.id, task)).collect::<sqlx::Result<Vec<_>>>();
+    let statements = statements.map(|mut statements| {
+        statements.push(set_counter(&user.id, "next_task_id", user.next_task_id.load(Ordering::SeqCst)));
+        statements
+    });
+    write(user, statements).await
+}
+
+pub async fn save_task(user: &User, task: &Task) -> sqlx::Result<()> {
+    save_tasks(user, std::slice::from_ref(task)).await
+}
+
+pub async fn save_tasks(user: &User, tasks: &[Task]) -> sqlx::Result<()> {
+    let statements = tasks.iter().map(|task| upsert_task(&user.id, task)).collect();
+    write(user, statements).await
+}
+
+pub async fn delete_tasks(user: &User, ids: &[u32]) -> sqlx::Result<()> {
+    let statements = ids
+        .iter()
+        .map(|&id| sqlx::query("DELETE FROM tasks WHERE user_id = ? AND id = ?").bind(&user.id).bind(id))
+        .collect();
+    write(user, Ok(statements)).await
+}
+
+// Archived tasks keep their rows, flagged so they load into `archived_tasks`
+pub async fn archive_tasks(user: &User, ids: &[u32]) -> sqlx::Result<()> {
+    let statements = ids
+        .iter()
+        .map(|&id| {
+            sqlx::query("UPDATE tasks SET archived = 1 WHERE user_id = ? AND id = ?").bind(&user.id).bind(id)
+        })
+        .collect();
+    write(user, Ok(statements)).await
+}
+
+// A comment that was just given an id, along with the advanced counter
+pub async fn insert_comment(user: &User, comment: &Comment) -> sqlx::Result<()> {
+    let statements = upsert_comment(&user.id, comment).map(|statement| {
+        vec![statement, set_counter(&user.id, "next_comment_id", user.next_comment_id.load(Ordering::SeqCst))]
+    });
+    write(user, statements).await
+}
+
+pub async fn save_comment(user: &User, comment: &Comment) -> sqlx::Result<()> {
+    write(user, upsert_comment(&user.id, comment).map(|statement| vec![statement])).await
+}
+
+pub async fn delete_comment(user: &User, id: u32) -> sqlx::Result<()> {
+    let statement = sqlx::query("DELETE FROM comments WHERE user_id = ? AND id = ?").bind(&user.id).bind(id);
+    write(user, Ok(vec![statement])).await
+}
+
+pub async fn save_goal(user: &User, goal: &Goal) -> sqlx::Result<()> {
+    save_goals(user, std::slice::from_ref(goal)).await
+}
+
+pub async fn save_goals(user: &User, goals: &[Goal]) -> sqlx::Result<()> {
+    let statements = goals.iter().map(|goal| upsert_goal(&user.id, goal)).collect();
+    write(user, statements).await
+}
+
+pub async fn delete_goal(user: &User, id: Uuid) -> sqlx::Result<()> {
+    let statement = sqlx::query("DELETE FROM goals WHERE user_id = ? AND id = ?").bind(&user.id).bind(id.to_string());
+    write(user, Ok(vec![statement])).await
+}
+
+// Rows added by /import; with `replace` everything the user had stored, apart from
+// archived tasks, goes first
+pub async fn import(
+    user: &User,
+    replace: bool,
+    tasks: &[Task],
+    comments: &[Comment],
+    goals: &[Goal],
+) -> sqlx::Result<()> {
+    let statements = (|| {
+        let mut statements = Vec::new();
+        if replace {
+            for sql in [
+                "DELETE FROM tasks WHERE user_id = ? AND archived = 0",
+                "DELETE FROM comments WHERE user_id = ?",
+                "DELETE FROM goals WHERE user_id = ?",
+            ] {
+                statements.push(sqlx::query(sql).bind(&user.id));
+            }
+        }
+        for task in tasks {
+            statements.push(upsert_task(&user.id, task)?);
+        }
+        for comment in comments {
+            statements.push(upsert_comment(&user.id, comment)?);
+        }
+        for goal in goals {
+            statements.push(upsert_goal(&user.id, goal)?);
+        }
+        statements.push(set_counter(&user.id, "next_task_id", user.next_task_id.load(Ordering::SeqCst)));
+        statements.push(set_counter(&user.id, "next_comment_id", user.next_comment_id.load(Ordering::SeqCst)));
+        Ok(statements)
+    })();
+    write(user, statements).await
+}
+
+pub async fn save_bot_task(data: &BotAppState, task: &BotTask) -> sqlx::Result<()> {
+    let statement = sqlx::query(
+        "INSERT INTO bot_tasks (id, title, completed, is_pomodoro) VALUES (?, ?, ?, ?) \
+         ON CONFLICT (id) DO UPDATE SET title = excluded.title, completed = excluded.completed, \
+         is_pomodoro = excluded.is_pomodoro",
+    )
+    .bind(task.id.map(|id| id.to_string()))
+    .bind(&task.title)
+    .bind(task.completed)
+    .bind(task.is_pomodoro);
+    write_bot(data, vec![statement]).await
+}
+
+pub async fn delete_bot_tasks(data: &BotAppState, ids: &[Uuid]) -> sqlx::Result<()> {
+    let statements = ids
+        .iter()
+        .map(|id| sqlx::query("DELETE FROM bot_tasks WHERE id = ?").bind(id.to_string()))
+        .collect();
+    write_bot(data, statements).await
+}
+
+pub async fn save_bot_goal(data: &BotAppState, goal: &BotGoal) -> sqlx::Result<()> {
+    let statement = sqlx::query(
+        "INSERT INTO bot_goals (id, title, progress) VALUES (?, ?, ?) \
+         ON CONFLICT (id) DO UPDATE SET title = excluded.title, progress = excluded.progress",
+    )
+    .bind(goal.id.map(|id| id.to_string()))
+    .bind(&goal.title)
+    .bind(goal.progress);
+    write_bot(data, vec![statement]).await
+}
+
+pub async fn delete_bot_goal(data: &BotAppState, id: Uuid) -> sqlx::Result<()> {
+    write_bot(data, vec![sqlx::query("DELETE FROM bot_goals WHERE id = ?").bind(id.to_string())]).await
+}
+
+async fn write(user: &User, statements: sqlx::Result<Vec<Statement<'_>>>) -> sqlx::Result<()> {
+    let result = run(&user.db, statements).await;
+    if let Err(e) = &result {
+        log::error!("Failed to save data of user {}: {}", user.id, e);
+        match read_data(&user.db, &user.id).await {
+            Ok(stored) => {
+                *lock(&user.tasks) = stored.tasks;
+                *lock(&user.archived_tasks) = stored.archived_tasks;
+                *lock(&user.comments) = stored.comments;
+                *lock(&user.goals) = stored.goals;
+            }
+            Err(e) => log::error!("Failed to reload data of user {}: {}", user.id, e),
+        }
+    }
+    result
+}
+
+async fn write_bot(data: &BotAppState, statements: Vec<Statement<'_>>) -> sqlx::Result<()> {
+    let result = run(&data.db, Ok(statements)).await;
+    if let Err(e) = &result {
+        log::error!("Failed to save bot data: {}", e);
+        match read_bot_data(&data.db).await {
+            Ok((tasks, goals)) => {
+                *lock(&data.tasks) = tasks;
+                *lock(&data.goals) = goals;
+            }
+            Err(e) => log::error!("Failed to reload bot data: {}", e),
+        }
+    }
+    result
+}
+
+async fn run(pool: &SqlitePool, statements: sqlx::Result<Vec<Statement<'_>>>) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    for statement in statements? {
+        statement.execute(&mut *tx).await?;
+    }
+    tx.commit().await
+}
+
+fn set_counter<'q>(user_id: &'q str, name: &'q str, value: u32) -> Statement<'q> {
+    sqlx::query(
+        "INSERT INTO counters (user_id, name, value) VALUES (?, ?, ?) \
+         ON CONFLICT (user_id, name) DO UPDATE SET value = excluded.value",
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(value)
+}
+
+fn upsert_task<'q>(user_id: &'q str, task: &'q Task) -> sqlx::Result<Statement<'q>> {
+    Ok(sqlx::query(
+        "INSERT INTO tasks (user_id, id, title, date, completed, priority, recurrence, tags, \
+         created_at, updated_at, description, deleted, start_time, end_time) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (user_id, id) DO UPDATE SET title = excluded.title, date = excluded.date, \
+         completed = excluded.completed, priority = excluded.priority, recurrence = excluded.recurrence, \
+         tags = excluded.tags, created_at = excluded.created_at, updated_at = excluded.updated_at, \
+         description = excluded.description, deleted = excluded.deleted, \
+         start_time = excluded.start_time, end_time = excluded.end_time",
+    )
+    .bind(user_id)
+    .bind(task.id)
+    .bind(&task.title)
+    .bind(&task.date)
+    .bind(task.completed)
+    .bind(to_text(&task.priority)?)
+    .bind(task.recurrence.as_ref().map(to_text).transpose()?)
+    .bind(to_json(&task.tags)?)
+    .bind(&task.created_at)
+    .bind(&task.updated_at)
+    .bind(&task.description)
+    .bind(task.deleted)
+    .bind(&task.start_time)
+    .bind(&task.end_time))
+}
+
+fn upsert_comment<'q>(user_id: &'q str, comment: &'q Comment) -> sqlx::Result<Statement<'q>> {
+    Ok(sqlx::query(
+        "INSERT INTO comments (user_id, id, title, content, version, created_at) VALUES (?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (user_id, id) DO UPDATE SET title = excluded.title, content = excluded.content, \
+         version = excluded.version, created_at = excluded.created_at",
+    )
+    .bind(user_id)
+    .bind(comment.id)
+    .bind(&comment.title)
+    .bind(&comment.content)
+    .bind(comment.version)
+    .bind(&comment.created_at))
+}
+
+fn upsert_goal<'q>(user_id: &'q str, goal: &'q Goal) -> sqlx::Result<Statement<'q>> {
+    Ok(sqlx::query(
+        "INSERT INTO goals (user_id, id, title, description, priority, due_date, progress, sub_goals, history) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (user_id, id) DO UPDATE SET title = excluded.title, description = excluded.description, \
+         priority = excluded.priority, due_date = excluded.due_date, progress = excluded.progress, \
+         sub_goals = excluded.sub_goals, history = excluded.history",
+    )
+    .bind(user_id)
+    .bind(goal.id.to_string())
+    .bind(&goal.title)
+    .bind(&goal.description)
+    .bind(to_text(&goal.priority)?)
+    .bind(&goal.due_date)
+    .bind(goal.progress)
+    .bind(to_json(&goal.sub_goals)?)
+    .bind(to_json(&goal.history)?))
+}
+
+fn task_from_row(row: &SqliteRow) -> sqlx::Result<Task> {
+    Ok(Task {
+        id: row.try_get("id")?,
+        title: row.try_get("title")?,
+        date: row.try_get("date")?,
+        completed: row.try_get("completed")?,
+        priority: from_text(row.try_get("priority")?)?,
+        recurrence: row.try_get::<Option<String>, _>("recurrence")?.map(from_text).transpose()?,
+        tags: from_json(row.try_get("tags")?)?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        description: row.try_get("description")?,
+        deleted: row.try_get("deleted")?,
+        start_time: row.try_get("start_time")?,
+        end_time: row.try_get("end_time")?,
+    })
+}
+
+fn comment_from_row(row: &SqliteRow) -> sqlx::Result<Comment> {
+    Ok(Comment {
+        id: row.try_get("id")?,
+        title: row.try_get("title")?,
+        content: row.try_get("content")?,
+        version: row.try_get("version")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn goal_from_row(row: &SqliteRow) -> sqlx::Result<Goal> {
+    Ok(Goal {
+        id: parse_uuid(row.try_get("id")?)?,
+        title: row.try_get("title")?,
+        description: row.try_get("description")?,
+        priority: from_text(row.try_get("priority")?)?,
+        due_date: row.try_get("due_date")?,
+        progress: row.try_get("progress")?,
+        sub_goals: from_json(row.try_get("sub_goals")?)?,
+        history: from_json(row.try_get("history")?)?,
+    })
+}
+
+fn bot_task_from_row(row: &SqliteRow) -> sqlx::Result<BotTask> {
+    Ok(BotTask {
+        id: row.try_get::<Option<String>, _>("id")?.map(parse_uuid).transpose()?,
+        title: row.try_get("title")?,
+        completed: row.try_get("completed")?,
+        is_pomodoro: row.try_get("is_pomodoro")?,
+    })
+}
+
+fn bot_goal_from_row(row: &SqliteRow) -> sqlx::Result<BotGoal> {
+    Ok(BotGoal {
+        id: row.try_get::<Option<String>, _>("id")?.map(parse_uuid).transpose()?,
+        title: row.try_get("title")?,
+        progress: row.try_get("progress")?,
+    })
+}
+
+fn decode_error(e: impl std::error::Error + Send + Sync + 'static) -> sqlx::Error {
+    sqlx::Error::Decode(Box::new(e))
+}
+
+fn parse_uuid(value: String) -> sqlx::Result<Uuid> {
+    Uuid::parse_str(&value).map_err(decode_error)
+}
+
+// Enums are stored as their serde spelling, e.g. "high" or "weekly"
+fn to_text<T: serde::Serialize>(value: &T) -> sqlx::Result<String> {
+    match serde_json::to_value(value).map_err(decode_error)? {
+        serde_json::Value::String(text) => Ok(text),
+        other => Ok(other.to_string()),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(text: String) -> sqlx::Result<T> {
+    serde_json::from_value(serde_json::Value::String(text)).map_err(decode_error)
+}
+
+// Lists such as tags and sub-goals are stored as JSON text
+fn to_json<T: serde::Serialize>(value: &T) -> sqlx::Result<String> {
+    serde_json::to_string(value).map_err(decode_error)
+}
+
+fn from_json<T: DeserializeOwned>(json: String) -> sqlx::Result<T> {
+    serde_json::from_str(&json).map_err(decode_error)
+}
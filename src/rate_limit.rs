@@ -0,0 +1,79 @@
+use actix_web::{web, Error, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{lock, ApiError};
+
+const WINDOW: Duration = Duration::from_secs(60);
+// Forget clients whose window has expired once this many are being tracked
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// Fixed one-minute window per client IP
+pub struct RateLimiter {
+    max_per_minute: u32,
+    clients: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_per_minute: u32) -> Self {
+        RateLimiter {
+            max_per_minute,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Counts the request and returns whether it is still within the limit
+    fn allow(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut clients = lock(&self.clients);
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, (_, window_start)| now.duration_since(*window_start) < WINDOW);
+        }
+
+        let (count, window_start) = clients.entry(ip).or_insert((0, now));
+        if now.duration_since(*window_start) >= WINDOW {
+            *count = 0;
+            *window_start = now;
+        }
+        *count += 1;
+        *count <= self.max_per_minute
+    }
+}
+
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limited = match (req.app_data::<web::Data<RateLimiter>>(), req.peer_addr()) {
+        (Some(limiter), Some(addr)) => !limiter.allow(addr.ip()),
+        _ => false,
+    };
+
+    if limited {
+        let body = ApiError { error: "rate_limited", detail: "Too many requests, try again later".to_string() };
+        return Ok(req.into_response(HttpResponse::TooManyRequests().json(body)).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_requests_over_the_limit() {
+        let limiter = RateLimiter::new(3);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        for _ in 0..3 {
+            assert!(limiter.allow(ip));
+        }
+        assert!(!limiter.allow(ip));
+        // Other clients have their own window
+        assert!(limiter.allow("127.0.0.2".parse().unwrap()));
+    }
+}
@@ -0,0 +1,95 @@
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header::HeaderName;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::audit::AuditLog;
+use crate::{lock, ApiError, AppState, UserData};
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-user-id");
+
+// Requests without an id, and data saved before users existed, belong to this user
+pub const DEFAULT_USER: &str = "default";
+
+const MAX_ID_LEN: usize = 64;
+
+// The data of the user a request is made for, taken from the X-User-Id header.
+// WebSocket clients can't set headers, so `?user_id=` is accepted instead.
+//
+// The id is taken on trust: it isn't tied to API_KEY or any other credential, so any
+// client that can reach the API can read and change any user's data by sending their id.
+// It separates the data of people sharing a deployment, it doesn't protect it.
+pub(crate) struct User {
+    pub id: String,
+    pub db: SqlitePool,
+    pub audit: Arc<AuditLog>,
+    data: Arc<UserData>,
+}
+
+impl User {
+    // Adds the user to AppState if they aren't there yet, so what they store is kept.
+    // Until then a user only has the empty data they were given for reading.
+    pub fn register(self, state: &AppState) -> User {
+        let data = lock(&state.users).entry(self.id.clone()).or_insert(self.data).clone();
+        User { data, ..self }
+    }
+}
+
+impl Deref for User {
+    type Target = UserData;
+
+    fn deref(&self) -> &UserData {
+        &self.data
+    }
+}
+
+impl FromRequest for User {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<User, actix_web::Error>>;
+
+    // Reads (GET, HEAD, ...) from a user that hasn't stored anything see empty collections
+    // without being added to AppState; any other request registers the user
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(user_id(req).and_then(|id| {
+            let Some(state) = req.app_data::<web::Data<AppState>>() else {
+                return Err(actix_web::error::ErrorInternalServerError("AppState is not configured"));
+            };
+            let existing = lock(&state.users).get(&id).cloned();
+            let user = User {
+                data: existing.unwrap_or_default(),
+                db: state.db.clone(),
+                audit: Arc::clone(&state.audit),
+                id,
+            };
+            Ok(if req.method().is_safe() { user } else { user.register(state) })
+        }))
+    }
+}
+
+fn user_id(req: &HttpRequest) -> Result<String, actix_web::Error> {
+    let id = match req.headers().get(&HEADER) {
+        Some(value) => value.to_str().map(str::to_string).unwrap_or_default(),
+        None => match web::Query::<HashMap<String, String>>::from_query(req.query_string()) {
+            Ok(query) => query.get("user_id").cloned().unwrap_or_else(|| DEFAULT_USER.to_string()),
+            Err(_) => DEFAULT_USER.to_string(),
+        },
+    };
+    let id = id.trim();
+    let valid = !id.is_empty()
+        && id.len() <= MAX_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'));
+    if !valid {
+        let detail = format!(
+            "User id must be 1-{} letters, digits or any of - _ . @",
+            MAX_ID_LEN
+        );
+        let body = ApiError { error: "invalid_user", detail: detail.clone() };
+        return Err(InternalError::from_response(detail, HttpResponse::BadRequest().json(body)).into());
+    }
+    Ok(id.to_string())
+}
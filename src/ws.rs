@@ -0,0 +1,65 @@
+use actix_ws::{Message, MessageStream, Session};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::lock;
+
+// Connected WebSocket clients that get a copy of every broadcast event
+pub struct Broadcaster {
+    sessions: Arc<Mutex<Vec<(u64, Session)>>>,
+    next_id: AtomicU64,
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Broadcaster {
+            sessions: Arc::new(Mutex::new(Vec::new())),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    // Keeps the session for broadcasts and answers pings until the client disconnects,
+    // then drops it so closed sessions don't pile up between broadcasts
+    pub fn subscribe(&self, session: Session, mut stream: MessageStream) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        lock(&self.sessions).push((id, session.clone()));
+
+        let sessions = Arc::clone(&self.sessions);
+        let mut session = session;
+        actix_web::rt::spawn(async move {
+            while let Some(Ok(msg)) = stream.recv().await {
+                match msg {
+                    Message::Ping(bytes) if session.pong(&bytes).await.is_err() => break,
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            lock(&sessions).retain(|(session_id, _)| *session_id != id);
+            let _ = session.close(None).await;
+        });
+    }
+
+    // Sends the event as JSON to every client, dropping the ones that have gone away.
+    // The sends run on a spawned task so handlers never wait on slow clients.
+    pub fn broadcast<T: Serialize>(&self, event: &T) {
+        let Ok(msg) = serde_json::to_string(event) else {
+            return;
+        };
+
+        let sessions = Arc::clone(&self.sessions);
+        actix_web::rt::spawn(async move {
+            let snapshot = lock(&sessions).clone();
+            let mut closed = Vec::new();
+            for (id, mut session) in snapshot {
+                if session.text(msg.clone()).await.is_err() {
+                    closed.push(id);
+                }
+            }
+
+            if !closed.is_empty() {
+                lock(&sessions).retain(|(id, _)| !closed.contains(id));
+            }
+        });
+    }
+}